@@ -0,0 +1,127 @@
+mod codel;
+mod pie;
+
+use std::fmt;
+
+use rand::{rngs::StdRng, Rng};
+
+use crate::{
+    entities::bottleneck::RedConfig,
+    packet::Packet,
+    time::Time,
+    units::{Bytes, Nanosecs},
+};
+
+pub use codel::CoDelConfig;
+pub use pie::PieConfig;
+
+use self::{codel::CoDel, pie::Pie};
+
+// An active queue management policy for a single queue.
+pub(crate) trait Aqm: fmt::Debug {
+    // Called before `pkt` is enqueued into a queue currently holding `qsize` bytes.
+    fn on_enqueue(
+        &mut self,
+        _pkt: &Packet,
+        _qsize: Bytes,
+        _now: Time,
+        _rng: &mut StdRng,
+    ) -> Verdict {
+        Verdict::Pass
+    }
+
+    // Called after `pkt` is dequeued, leaving `qsize` bytes in the queue.
+    fn on_dequeue(&mut self, pkt: &Packet, qsize: Bytes, now: Time, rng: &mut StdRng) -> Verdict;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Verdict {
+    Pass,
+    Mark,
+    Drop,
+}
+
+impl Verdict {
+    // Marks instead of dropping if `ecn` is set.
+    fn drop_or_mark(ecn: bool) -> Self {
+        if ecn {
+            Verdict::Mark
+        } else {
+            Verdict::Drop
+        }
+    }
+}
+
+/// An active queue management policy.
+#[derive(Debug, Default, Clone, Copy, serde::Serialize, serde::Deserialize)]
+pub enum AqmKind {
+    /// ECN marking when the queue size exceeds the DCTCP marking threshold, or RED-style
+    /// probabilistic marking if configured.
+    #[default]
+    Ecn,
+    /// CoDel, as described in RFC 8289.
+    CoDel(CoDelConfig),
+    /// PIE, as described in RFC 8033.
+    Pie(PieConfig),
+}
+
+impl AqmKind {
+    pub(crate) fn build(self, marking_threshold: Bytes, red: Option<RedConfig>) -> Box<dyn Aqm> {
+        match self {
+            AqmKind::Ecn => Box::new(Ecn {
+                marking_threshold,
+                red,
+            }),
+            AqmKind::CoDel(cfg) => Box::new(CoDel::new(cfg)),
+            AqmKind::Pie(cfg) => Box::new(Pie::new(cfg)),
+        }
+    }
+
+    // Whether the policy's delays and intervals are usable.
+    pub(crate) fn is_valid(self) -> bool {
+        match self {
+            AqmKind::Ecn => true,
+            AqmKind::CoDel(cfg) => cfg.target > Nanosecs::ZERO && cfg.interval > Nanosecs::ZERO,
+            AqmKind::Pie(cfg) => cfg.t_update > Nanosecs::ZERO,
+        }
+    }
+
+    // Whether the policy may drop packets.
+    pub(crate) fn can_drop(self) -> bool {
+        match self {
+            AqmKind::Ecn => false,
+            AqmKind::CoDel(cfg) => !cfg.ecn,
+            AqmKind::Pie(_) => true,
+        }
+    }
+}
+
+// Marking based on the instantaneous queue size
+#[derive(Debug, Clone, Copy)]
+struct Ecn {
+    marking_threshold: Bytes,
+    red: Option<RedConfig>,
+}
+
+impl Aqm for Ecn {
+    fn on_dequeue(&mut self, _pkt: &Packet, qsize: Bytes, _now: Time, rng: &mut StdRng) -> Verdict {
+        let marked = match self.red {
+            None => qsize > self.marking_threshold,
+            Some(RedConfig { kmin, kmax, pmax }) => {
+                if qsize <= kmin {
+                    false
+                } else if qsize > kmax {
+                    true
+                } else {
+                    let p = pmax * Bytes::frac(qsize - kmin, kmax - kmin);
+                    rng.gen_bool(p.clamp(0.0, 1.0))
+                }
+            }
+        };
+        if marked {
+            Verdict::Mark
+        } else {
+            Verdict::Pass
+        }
+    }
+}
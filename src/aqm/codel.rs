@@ -0,0 +1,106 @@
+use rand::rngs::StdRng;
+
+use crate::{
+    packet::Packet,
+    time::Time,
+    units::{Bytes, Microsecs, Nanosecs},
+};
+
+use super::{Aqm, Verdict};
+
+/// CoDel parameters.
+///
+/// The defaults are the values recommended by RFC 8289 with time scaled down by a factor of 1000
+/// to match datacenter delays.
+#[derive(Debug, Clone, Copy, typed_builder::TypedBuilder, serde::Serialize, serde::Deserialize)]
+pub struct CoDelConfig {
+    /// The acceptable standing queue delay.
+    #[builder(default = Microsecs::new(5).into_ns(), setter(into))]
+    pub target: Nanosecs,
+    /// The window over which the queue delay must stay above `target` before packets are
+    /// dropped.
+    #[builder(default = Microsecs::new(100).into_ns(), setter(into))]
+    pub interval: Nanosecs,
+    /// Whether to ECN-mark packets instead of dropping them.
+    #[builder(default)]
+    pub ecn: bool,
+}
+
+impl Default for CoDelConfig {
+    fn default() -> Self {
+        Self::builder().build()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub(super) struct CoDel {
+    cfg: CoDelConfig,
+    // When the sojourn time will have been above target for an interval, if it is above target
+    first_above_time: Option<Time>,
+    dropping: bool,
+    drop_next: Time,
+    count: u32,
+    last_count: u32,
+}
+
+impl CoDel {
+    pub(super) fn new(cfg: CoDelConfig) -> Self {
+        Self {
+            cfg,
+            first_above_time: None,
+            dropping: false,
+            drop_next: Time::ZERO,
+            count: 0,
+            last_count: 0,
+        }
+    }
+
+    // The next drop time is `interval / sqrt(count)` after `t`.
+    fn control_law(&self, t: Time) -> Time {
+        let interval = self.cfg.interval.into_f64() / f64::from(self.count).sqrt();
+        t + Nanosecs::new(interval.round() as u64).into_delta()
+    }
+}
+
+impl Aqm for CoDel {
+    fn on_dequeue(&mut self, pkt: &Packet, qsize: Bytes, now: Time, _rng: &mut StdRng) -> Verdict {
+        let sojourn = (now - pkt.enqueued_at).into_ns();
+        let ok_to_drop = if sojourn < self.cfg.target || qsize <= pkt.size {
+            // The queue delay is below target, or there is less than a packet's worth of queue
+            self.first_above_time = None;
+            false
+        } else {
+            match self.first_above_time {
+                None => {
+                    self.first_above_time = Some(now + self.cfg.interval.into_delta());
+                    false
+                }
+                Some(t) => now >= t,
+            }
+        };
+        if self.dropping {
+            if !ok_to_drop {
+                self.dropping = false;
+                return Verdict::Pass;
+            }
+            if now >= self.drop_next {
+                self.count += 1;
+                self.drop_next = self.control_law(self.drop_next);
+                return Verdict::drop_or_mark(self.cfg.ecn);
+            }
+            Verdict::Pass
+        } else if ok_to_drop {
+            self.dropping = true;
+            // Resume at a higher drop rate if the last dropping state ended recently
+            let delta = self.count.saturating_sub(self.last_count);
+            let recently =
+                now.saturating_sub(self.drop_next).into_ns() < self.cfg.interval.scale_by(16.0);
+            self.count = if delta > 1 && recently { delta } else { 1 };
+            self.drop_next = self.control_law(now);
+            self.last_count = self.count;
+            Verdict::drop_or_mark(self.cfg.ecn)
+        } else {
+            Verdict::Pass
+        }
+    }
+}
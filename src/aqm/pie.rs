@@ -0,0 +1,136 @@
+use rand::{rngs::StdRng, Rng};
+
+use crate::{
+    packet::Packet,
+    time::{Delta, Time},
+    units::{Bytes, Microsecs, Nanosecs},
+};
+
+use super::{Aqm, Verdict};
+
+/// PIE parameters.
+///
+/// The defaults are the values recommended by RFC 8033 with time scaled down by a factor of 1000
+/// to match datacenter delays. `alpha` and `beta` are scaled up accordingly.
+#[derive(Debug, Clone, Copy, typed_builder::TypedBuilder, serde::Serialize, serde::Deserialize)]
+pub struct PieConfig {
+    /// The target queue delay.
+    #[builder(default = Microsecs::new(15).into_ns(), setter(into))]
+    pub target: Nanosecs,
+    /// The interval between drop probability updates.
+    #[builder(default = Microsecs::new(15).into_ns(), setter(into))]
+    pub t_update: Nanosecs,
+    /// The weight of the deviation from the target delay, per second.
+    #[builder(default = 125.0)]
+    pub alpha: f64,
+    /// The weight of the change in queue delay, per second.
+    #[builder(default = 1250.0)]
+    pub beta: f64,
+    /// The burst allowance after an idle period.
+    #[builder(default = Microsecs::new(150).into_ns(), setter(into))]
+    pub max_burst: Nanosecs,
+    /// Whether to ECN-mark packets instead of dropping them while the drop probability is below
+    /// `mark_ecn_threshold`.
+    #[builder(default)]
+    pub ecn: bool,
+    /// The drop probability above which packets are dropped even if `ecn` is set.
+    #[builder(default = 0.1)]
+    pub mark_ecn_threshold: f64,
+}
+
+impl Default for PieConfig {
+    fn default() -> Self {
+        Self::builder().build()
+    }
+}
+
+const MAX_UPDATES_AT_ONCE: usize = 1_000;
+
+#[derive(Debug, Clone)]
+pub(super) struct Pie {
+    cfg: PieConfig,
+    drop_prob: f64,
+    // The delay of the most recently dequeued packet, in seconds
+    qdelay: f64,
+    qdelay_old: f64,
+    burst_allowance: Nanosecs,
+    next_update: Time,
+}
+
+impl Pie {
+    pub(super) fn new(cfg: PieConfig) -> Self {
+        Self {
+            cfg,
+            drop_prob: 0.0,
+            qdelay: 0.0,
+            qdelay_old: 0.0,
+            burst_allowance: cfg.max_burst,
+            next_update: cfg.t_update.into_time(),
+        }
+    }
+
+    // Runs the periodic drop probability updates that are due by `now`.
+    fn update(&mut self, now: Time) {
+        let target = self.cfg.target.into_f64() / 1e9;
+        let mut nr_updates = 0;
+        while self.next_update <= now {
+            if nr_updates == MAX_UPDATES_AT_ONCE {
+                // After a long idle period, the state has settled; skip the remaining updates
+                let period = self.cfg.t_update.into_delta().into_u128();
+                let nr_missed = (now - self.next_update).into_u128() / period + 1;
+                self.next_update += Delta::new(nr_missed * period);
+                break;
+            }
+            nr_updates += 1;
+            let mut p = self.cfg.alpha * (self.qdelay - target)
+                + self.cfg.beta * (self.qdelay - self.qdelay_old);
+            // Auto-tune the step size to the current drop probability
+            p /= match self.drop_prob {
+                x if x < 0.000001 => 2048.0,
+                x if x < 0.00001 => 512.0,
+                x if x < 0.0001 => 128.0,
+                x if x < 0.001 => 32.0,
+                x if x < 0.01 => 8.0,
+                x if x < 0.1 => 2.0,
+                _ => 1.0,
+            };
+            self.drop_prob += p;
+            if self.qdelay == 0.0 && self.qdelay_old == 0.0 {
+                self.drop_prob *= 0.98;
+            }
+            self.drop_prob = self.drop_prob.clamp(0.0, 1.0);
+            self.burst_allowance = self.burst_allowance.saturating_sub(self.cfg.t_update);
+            if self.drop_prob == 0.0 && self.qdelay < target / 2.0 && self.qdelay_old < target / 2.0
+            {
+                self.burst_allowance = self.cfg.max_burst;
+            }
+            self.qdelay_old = self.qdelay;
+            self.next_update += self.cfg.t_update.into_delta();
+        }
+    }
+}
+
+impl Aqm for Pie {
+    fn on_enqueue(&mut self, pkt: &Packet, qsize: Bytes, now: Time, rng: &mut StdRng) -> Verdict {
+        self.update(now);
+        let target = self.cfg.target.into_f64() / 1e9;
+        let is_safe = self.burst_allowance > Nanosecs::ZERO
+            || (self.qdelay_old < target / 2.0 && self.drop_prob < 0.2)
+            || qsize <= pkt.size.scale_by(2.0);
+        if is_safe || !rng.gen_bool(self.drop_prob) {
+            return Verdict::Pass;
+        }
+        Verdict::drop_or_mark(self.cfg.ecn && self.drop_prob <= self.cfg.mark_ecn_threshold)
+    }
+
+    fn on_dequeue(&mut self, pkt: &Packet, qsize: Bytes, now: Time, _rng: &mut StdRng) -> Verdict {
+        self.update(now);
+        // Queue delay is estimated from timestamps
+        self.qdelay = if qsize == Bytes::ZERO {
+            0.0
+        } else {
+            (now - pkt.enqueued_at).into_f64() / 1e9
+        };
+        Verdict::Pass
+    }
+}
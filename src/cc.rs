@@ -0,0 +1,162 @@
+//! Congestion control algorithms.
+//!
+//! Every flow owns a [`CongestionControl`] instance which determines how fast (rate) and how much
+//! (window) the flow may send. The algorithm used by a flow is selected with [`CcKind`], either
+//! globally in the [configuration](crate::Config), per [source](crate::SourceDesc), or per
+//! [flow](crate::FlowDesc).
+
+mod dcqcn;
+mod dctcp;
+mod hpcc;
+mod swift;
+mod timely;
+
+use std::fmt;
+
+use crate::{
+    packet::IntHop,
+    time::Time,
+    units::{BitsPerSec, Bytes, Nanosecs},
+};
+
+pub use dcqcn::{Dcqcn, DcqcnConfig};
+pub use dctcp::{Dctcp, WindowDctcp};
+pub use hpcc::{Hpcc, HpccConfig};
+pub use swift::{Swift, SwiftConfig};
+pub use timely::{Timely, TimelyConfig};
+
+/// A per-flow congestion control algorithm.
+///
+/// Implementations own all of the rate and window state of a flow. The flow itself only keeps
+/// track of sequence numbers and asks the controller how much it may send, and when.
+pub trait CongestionControl: fmt::Debug {
+    /// Returns the current sending rate.
+    fn rate(&self) -> BitsPerSec;
+
+    /// Returns the current sending window.
+    fn window(&self) -> Bytes;
+
+    /// Called whenever the flow sends a packet of `size` bytes (including headers).
+    fn on_send(&mut self, _size: Bytes, _now: Time) {}
+
+    /// Called whenever the flow receives an ACK.
+    fn on_ack(&mut self, ack: &AckInfo);
+
+    /// Called when the flow detects a packet loss, either through a retransmission timeout or
+    /// through feedback from the receiver.
+    fn on_loss(&mut self, _now: Time) {}
+
+    /// Called when the timer returned by [`next_timer`](Self::next_timer) expires.
+    fn on_timer(&mut self, _now: Time) {}
+
+    /// Returns the next time at which [`on_timer`](Self::on_timer) should be called, if any.
+    fn next_timer(&self) -> Option<Time> {
+        None
+    }
+}
+
+/// The information about an ACK passed to a [`CongestionControl`] instance.
+#[derive(Debug, Clone, Copy)]
+#[non_exhaustive]
+pub struct AckInfo<'a> {
+    /// The time the ACK was received.
+    pub now: Time,
+    /// The number of bytes newly acknowledged by this ACK.
+    pub nr_bytes: Bytes,
+    /// Whether the acknowledged packet was ECN-marked. For an ACK that covers several packets,
+    /// whether any of them was.
+    pub marked: bool,
+    /// The number of acknowledged payload bytes that were ECN-marked.
+    pub marked_bytes: Bytes,
+    /// The round-trip time measured from the acknowledged packet's send time.
+    pub rtt: Nanosecs,
+    /// The flow's first unacknowledged byte, including this ACK.
+    pub snd_una: Bytes,
+    /// The flow's next byte to be sent.
+    pub snd_nxt: Bytes,
+    /// The maximum packet payload size.
+    pub sz_pktmax: Bytes,
+    /// The telemetry stamped by each bottleneck on the acknowledged packet's path, in path order.
+    pub int: &'a [IntHop],
+}
+
+/// The parameters with which a flow's [`CongestionControl`] instance is initialized.
+#[derive(Debug, Clone, Copy)]
+#[non_exhaustive]
+pub struct CcInit {
+    /// The time the flow starts.
+    pub now: Time,
+    /// The maximum sending rate, i.e., the rate of the source's link.
+    pub max_rate: BitsPerSec,
+    /// The initial sending rate, at most `max_rate`. Window-based algorithms start with the
+    /// window that sustains this rate over the base RTT if it is below `max_rate`.
+    pub rate: BitsPerSec,
+    /// The maximum sending window.
+    pub window: Bytes,
+    /// The base round-trip propagation delay.
+    pub base_rtt: Nanosecs,
+    /// The number of bottleneck links on the flow's path.
+    pub nr_hops: usize,
+    /// The maximum packet payload size.
+    pub sz_pktmax: Bytes,
+    /// The flow size.
+    pub size: Bytes,
+    /// The time by which the flow should complete, if it has a deadline.
+    pub deadline: Option<Time>,
+}
+
+/// A congestion control algorithm selection.
+#[derive(
+    Debug, Default, Clone, Copy, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize,
+)]
+pub enum CcKind {
+    /// Rate-based DCTCP.
+    #[default]
+    Dctcp,
+    /// Window-based DCTCP.
+    WindowDctcp,
+    /// D2TCP, i.e., rate-based DCTCP with deadline-aware rate reductions.
+    D2tcp,
+    /// DCQCN.
+    Dcqcn,
+    /// HPCC.
+    Hpcc,
+    /// TIMELY.
+    Timely,
+    /// Swift.
+    Swift,
+}
+
+impl CcKind {
+    pub(crate) fn build(self, init: CcInit, params: &CcParams) -> Box<dyn CongestionControl> {
+        match self {
+            CcKind::Dctcp => Box::new(Dctcp::new(init, params.dctcp_gain, params.dctcp_ai)),
+            CcKind::WindowDctcp => Box::new(WindowDctcp::new(
+                init,
+                params.dctcp_gain,
+                params.dctcp_pacing,
+            )),
+            CcKind::D2tcp => Box::new(Dctcp::d2tcp(init, params.dctcp_gain, params.dctcp_ai)),
+            CcKind::Dcqcn => Box::new(Dcqcn::new(init, params.dcqcn)),
+            CcKind::Hpcc => Box::new(Hpcc::new(init, params.hpcc)),
+            CcKind::Timely => Box::new(Timely::new(init, params.timely)),
+            CcKind::Swift => Box::new(Swift::new(init, params.swift)),
+        }
+    }
+}
+
+// Simulation-wide congestion control parameters.
+#[derive(Debug, Clone)]
+pub(crate) struct CcParams {
+    pub(crate) default: CcKind,
+    pub(crate) window: Bytes,
+    pub(crate) initial_rate: Option<BitsPerSec>,
+    pub(crate) initial_window: Option<Bytes>,
+    pub(crate) dctcp_gain: f64,
+    pub(crate) dctcp_ai: BitsPerSec,
+    pub(crate) dctcp_pacing: bool,
+    pub(crate) dcqcn: DcqcnConfig,
+    pub(crate) hpcc: HpccConfig,
+    pub(crate) timely: TimelyConfig,
+    pub(crate) swift: SwiftConfig,
+}
@@ -0,0 +1,289 @@
+use std::cmp;
+
+use crate::{
+    time::Time,
+    units::{BitsPerSec, Bytes, Mbps, Microsecs, Nanosecs},
+};
+
+use super::{AckInfo, CcInit, CongestionControl};
+
+/// DCQCN parameters.
+#[derive(Debug, Clone, Copy, typed_builder::TypedBuilder, serde::Serialize, serde::Deserialize)]
+pub struct DcqcnConfig {
+    /// The alpha gain.
+    #[builder(default = 1.0 / 256.0)]
+    pub gain: f64,
+    /// The interval after which alpha decays if no CNP has been received.
+    #[builder(default = Microsecs::new(55).into_ns(), setter(into))]
+    pub alpha_interval: Nanosecs,
+    /// The minimum interval between two CNPs generated for the same flow.
+    #[builder(default = Microsecs::new(50).into_ns(), setter(into))]
+    pub cnp_interval: Nanosecs,
+    /// The rate increase timer period.
+    #[builder(default = Microsecs::new(55).into_ns(), setter(into))]
+    pub increase_interval: Nanosecs,
+    /// The number of bytes sent that triggers a rate increase event.
+    #[builder(default = Bytes::new(10_000_000), setter(into))]
+    pub byte_counter: Bytes,
+    /// The number of increase events after which a flow leaves fast recovery.
+    #[builder(default = 5)]
+    pub fast_recovery_steps: usize,
+    /// The additive increase step.
+    #[builder(default = Mbps::new(40).into_bps(), setter(into))]
+    pub additive_inc: BitsPerSec,
+    /// The hyper increase step.
+    #[builder(default = Mbps::new(200).into_bps(), setter(into))]
+    pub hyper_inc: BitsPerSec,
+    /// The minimum sending rate.
+    #[builder(default = Mbps::new(100).into_bps(), setter(into))]
+    pub min_rate: BitsPerSec,
+}
+
+impl Default for DcqcnConfig {
+    fn default() -> Self {
+        Self::builder().build()
+    }
+}
+
+/// DCQCN, as described in "Congestion Control for Large-Scale RDMA Deployments" (SIGCOMM '15).
+///
+/// The notification point is emulated on the ACK path: a marked ACK is treated as a CNP, but at
+/// most one CNP is generated per `cnp_interval`.
+#[derive(Debug, Clone)]
+pub struct Dcqcn {
+    cfg: DcqcnConfig,
+    max_rate: BitsPerSec,
+    window: Bytes,
+
+    // Current and target rates
+    rc: BitsPerSec,
+    rt: BitsPerSec,
+
+    // Alpha is decayed lazily when the next CNP arrives
+    alpha: f64,
+    alpha_updated: Time,
+    last_cnp: Option<Time>,
+
+    // Rate increase state
+    next_increase: Option<Time>,
+    bytes_sent: Bytes,
+    timer_count: usize,
+    byte_count: usize,
+}
+
+impl Dcqcn {
+    /// Creates a new DCQCN instance.
+    pub fn new(init: CcInit, cfg: DcqcnConfig) -> Self {
+        Self {
+            cfg,
+            max_rate: init.max_rate,
+            window: init.window,
+            rc: init.rate,
+            rt: init.max_rate,
+            alpha: 1.0,
+            alpha_updated: init.now,
+            last_cnp: None,
+            // A flow that starts below the maximum rate recovers towards it
+            next_increase: (init.rate < init.max_rate)
+                .then(|| init.now + cfg.increase_interval.into_delta()),
+            bytes_sent: Bytes::ZERO,
+            timer_count: 0,
+            byte_count: 0,
+        }
+    }
+
+    fn rcv_cnp(&mut self, now: Time) {
+        // Apply the alpha decays that would have happened since the last update
+        let nr_decays = (now - self.alpha_updated).into_u128()
+            / u128::from(self.cfg.alpha_interval.into_u64()).max(1);
+        self.alpha *= (1.0 - self.cfg.gain).powi(nr_decays.min(i32::MAX as u128) as i32);
+        self.alpha = (1.0 - self.cfg.gain) * self.alpha + self.cfg.gain;
+        self.alpha_updated = now;
+
+        // Cut the rate
+        self.rt = self.rc;
+        self.rc = cmp::max(self.cfg.min_rate, self.rc.scale_by(1.0 - self.alpha / 2.0));
+
+        // Restart the rate increase state machine
+        self.bytes_sent = Bytes::ZERO;
+        self.timer_count = 0;
+        self.byte_count = 0;
+        self.next_increase = Some(now + self.cfg.increase_interval.into_delta());
+    }
+
+    fn increase(&mut self) {
+        let f = self.cfg.fast_recovery_steps;
+        let (lo, hi) = (
+            cmp::min(self.timer_count, self.byte_count),
+            cmp::max(self.timer_count, self.byte_count),
+        );
+        if hi < f {
+            // Fast recovery: move halfway towards the target rate
+        } else if lo < f {
+            // Additive increase
+            self.rt = self.rt.saturating_add(self.cfg.additive_inc);
+        } else {
+            // Hyper increase
+            let inc = self.cfg.hyper_inc.scale_by((lo - f + 1) as f64);
+            self.rt = self.rt.saturating_add(inc);
+        }
+        self.rt = cmp::min(self.rt, self.max_rate);
+        self.rc = BitsPerSec::new((self.rc.into_u64() + self.rt.into_u64()).div_ceil(2));
+        if self.rc == self.max_rate {
+            // Fully recovered; nothing left to increase until the next CNP
+            self.next_increase = None;
+        }
+    }
+}
+
+impl CongestionControl for Dcqcn {
+    fn rate(&self) -> BitsPerSec {
+        self.rc
+    }
+
+    fn window(&self) -> Bytes {
+        self.window
+    }
+
+    fn on_send(&mut self, size: Bytes, _now: Time) {
+        if self.next_increase.is_none() {
+            return;
+        }
+        self.bytes_sent += size;
+        while self.bytes_sent >= self.cfg.byte_counter && self.next_increase.is_some() {
+            self.bytes_sent -= self.cfg.byte_counter;
+            self.byte_count += 1;
+            self.increase();
+        }
+    }
+
+    fn on_ack(&mut self, ack: &AckInfo) {
+        if !ack.marked {
+            return;
+        }
+        let should_notify = self
+            .last_cnp
+            .is_none_or(|t| ack.now - t >= self.cfg.cnp_interval.into_delta());
+        if should_notify {
+            self.last_cnp = Some(ack.now);
+            self.rcv_cnp(ack.now);
+        }
+    }
+
+    fn on_timer(&mut self, now: Time) {
+        self.timer_count += 1;
+        self.next_increase = Some(now + self.cfg.increase_interval.into_delta());
+        self.increase();
+    }
+
+    fn next_timer(&self) -> Option<Time> {
+        self.next_increase
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::units::Gbps;
+
+    use super::*;
+
+    fn mk_dcqcn() -> Dcqcn {
+        let init = CcInit {
+            now: Time::ZERO,
+            max_rate: Gbps::new(10).into_bps(),
+            rate: Gbps::new(10).into_bps(),
+            window: Bytes::new(100_000),
+            base_rtt: Nanosecs::new(4_000),
+            nr_hops: 1,
+            sz_pktmax: Bytes::new(1_000),
+            size: Bytes::new(1_000_000),
+            deadline: None,
+        };
+        Dcqcn::new(init, DcqcnConfig::default())
+    }
+
+    fn mk_ack(now: Time, marked: bool) -> AckInfo<'static> {
+        AckInfo {
+            now,
+            nr_bytes: Bytes::new(1_000),
+            marked,
+            marked_bytes: if marked {
+                Bytes::new(1_000)
+            } else {
+                Bytes::ZERO
+            },
+            rtt: Nanosecs::new(4_000),
+            snd_una: Bytes::ZERO,
+            snd_nxt: Bytes::ZERO,
+            sz_pktmax: Bytes::new(1_000),
+            int: &[],
+        }
+    }
+
+    #[test]
+    fn cnp_cuts_rate_by_half_alpha() {
+        let mut cc = mk_dcqcn();
+        cc.on_ack(&mk_ack(Time::ZERO, true));
+        // alpha starts at 1 and stays at 1 after the first CNP
+        assert_eq!(cc.rate(), Gbps::new(5).into_bps());
+        assert!(cc.next_timer().is_some());
+    }
+
+    #[test]
+    fn cnps_are_rate_limited() {
+        let mut cc = mk_dcqcn();
+        cc.on_ack(&mk_ack(Time::ZERO, true));
+        let rate = cc.rate();
+        cc.on_ack(&mk_ack(Time::new(1_000), true));
+        assert_eq!(cc.rate(), rate);
+        cc.on_ack(&mk_ack(Microsecs::new(50).into_time(), true));
+        assert!(cc.rate() < rate);
+    }
+
+    #[test]
+    fn fast_recovery_converges_to_target() {
+        let mut cc = mk_dcqcn();
+        cc.on_ack(&mk_ack(Time::ZERO, true));
+        for _ in 0..DcqcnConfig::default().fast_recovery_steps {
+            let t = cc.next_timer().unwrap();
+            cc.on_timer(t);
+        }
+        // After five halvings of the gap, the rate is within 1/32 of the target
+        let gap = Gbps::new(10).into_bps() - cc.rate();
+        assert!(gap <= Gbps::new(5).into_bps().scale_by(1.0 / 32.0));
+    }
+
+    #[test]
+    fn increase_stops_at_max_rate() {
+        let mut cc = mk_dcqcn();
+        cc.on_ack(&mk_ack(Time::ZERO, true));
+        let mut nr_events = 0;
+        while let Some(t) = cc.next_timer() {
+            cc.on_timer(t);
+            nr_events += 1;
+            assert!(nr_events < 1_000);
+        }
+        assert_eq!(cc.rate(), Gbps::new(10).into_bps());
+    }
+
+    #[test]
+    fn low_initial_rate_recovers() {
+        let init = CcInit {
+            now: Time::ZERO,
+            max_rate: Gbps::new(10).into_bps(),
+            rate: Gbps::new(1).into_bps(),
+            window: Bytes::new(100_000),
+            base_rtt: Nanosecs::new(4_000),
+            nr_hops: 1,
+            sz_pktmax: Bytes::new(1_000),
+            size: Bytes::new(1_000_000),
+            deadline: None,
+        };
+        let mut cc = Dcqcn::new(init, DcqcnConfig::default());
+        assert_eq!(cc.rate(), Gbps::new(1).into_bps());
+        let t = cc.next_timer().unwrap();
+        cc.on_timer(t);
+        // Fast recovery moves halfway towards the maximum rate
+        assert_eq!(cc.rate(), Gbps::new(10).into_bps().scale_by(0.55));
+    }
+}
@@ -0,0 +1,368 @@
+use std::cmp;
+
+use crate::{
+    time::Time,
+    units::{BitsPerSec, Bytes, Nanosecs},
+    Packet,
+};
+
+use super::{AckInfo, CcInit, CongestionControl};
+
+/// A rate-based emulation of DCTCP, or of D2TCP if created with [`Dctcp::d2tcp`].
+///
+/// The sending window is scaled by the ratio of the current rate to the maximum rate.
+#[derive(Debug, Clone)]
+pub struct Dctcp {
+    rate: BitsPerSec,
+    min_rate: BitsPerSec,
+    max_rate: BitsPerSec,
+    window: Bytes,
+
+    additive_inc: BitsPerSec,
+    estimator: AlphaEstimator,
+    ca_state: CaState,
+    high_seq: Bytes,
+
+    // The flow's deadline, if it has one and reductions are deadline-aware
+    deadline: Option<Time>,
+    size: Bytes,
+}
+
+impl Dctcp {
+    /// Creates a new DCTCP instance with gain `gain` and additive increase `additive_inc`.
+    pub fn new(init: CcInit, gain: f64, additive_inc: BitsPerSec) -> Self {
+        Self {
+            rate: init.rate,
+            min_rate: BitsPerSec::new(1_000_000_000),
+            max_rate: init.max_rate,
+            window: init.window,
+            additive_inc,
+            estimator: AlphaEstimator::new(gain),
+            ca_state: CaState::default(),
+            high_seq: Bytes::ZERO,
+            deadline: None,
+            size: init.size,
+        }
+    }
+
+    /// Creates a new D2TCP instance with gain `gain` and additive increase `additive_inc`.
+    ///
+    /// D2TCP reduces the rate by a factor of `1 - alpha^d / 2` instead of `1 - alpha / 2`, where
+    /// `d` is the ratio of the time the flow needs to complete at its current rate to the time
+    /// left until its deadline, clamped to `[0.5, 2]`. Flows near their deadlines thus back off
+    /// less. Flows without deadlines behave as in DCTCP.
+    pub fn d2tcp(init: CcInit, gain: f64, additive_inc: BitsPerSec) -> Self {
+        Self {
+            deadline: init.deadline,
+            ..Self::new(init, gain, additive_inc)
+        }
+    }
+
+    // The fraction of the rate given up on a mark, halved
+    fn penalty(&self, ack: &AckInfo) -> f64 {
+        let alpha = self.estimator.alpha;
+        let Some(deadline) = self.deadline else {
+            return alpha;
+        };
+        let imminence = if ack.now < deadline {
+            // The rate averages three quarters of its peak over a sawtooth
+            let remaining = self.size.saturating_sub(ack.snd_una);
+            let time_needed = self.rate.length(remaining).into_f64() / 0.75;
+            time_needed / (deadline - ack.now).into_f64()
+        } else {
+            D2TCP_MAX_IMMINENCE
+        };
+        alpha.powf(imminence.clamp(D2TCP_MIN_IMMINENCE, D2TCP_MAX_IMMINENCE))
+    }
+}
+
+// The bounds on D2TCP's deadline imminence factor
+const D2TCP_MIN_IMMINENCE: f64 = 0.5;
+const D2TCP_MAX_IMMINENCE: f64 = 2.0;
+
+impl CongestionControl for Dctcp {
+    fn rate(&self) -> BitsPerSec {
+        self.rate
+    }
+
+    fn window(&self) -> Bytes {
+        self.window
+            .scale_by(BitsPerSec::frac(self.rate, self.max_rate))
+    }
+
+    fn on_loss(&mut self, _now: Time) {
+        self.rate = cmp::max(self.min_rate, self.rate.scale_by(0.5));
+    }
+
+    fn on_ack(&mut self, ack: &AckInfo) {
+        let new_batch = self.estimator.on_ack(ack);
+        if self.ca_state == CaState::One && ack.snd_una > self.high_seq {
+            self.ca_state = CaState::Zero;
+        }
+        if self.ca_state == CaState::Zero {
+            if ack.marked {
+                // Reduce rate
+                let new_rate = self.rate.scale_by(1.0 - self.penalty(ack) / 2.0);
+                self.rate = cmp::max(self.min_rate, new_rate);
+                self.ca_state = CaState::One;
+                self.high_seq = ack.snd_nxt;
+            }
+            if new_batch {
+                let new_rate = self.rate.saturating_add(self.additive_inc);
+                self.rate = cmp::min(self.max_rate, new_rate);
+            }
+        }
+    }
+}
+
+// Linux paces at 120% of the congestion window per RTT outside of slow start
+const PACING_GAIN: f64 = 1.2;
+
+/// Window-based DCTCP, as implemented in Linux.
+///
+/// The congestion window is cut by a factor of `1 - alpha / 2` at most once per window of data
+/// when marks are echoed, and otherwise grows by one packet per RTT. Packets are clocked out by
+/// ACKs at the maximum rate, or paced over the smoothed RTT if pacing is enabled.
+#[derive(Debug, Clone)]
+pub struct WindowDctcp {
+    max_rate: BitsPerSec,
+    sz_pktmax: f64,
+    max_cwnd: f64,
+    pacing: bool,
+
+    // Congestion window, in bytes
+    cwnd: f64,
+    srtt: Nanosecs,
+    estimator: AlphaEstimator,
+    ca_state: CaState,
+    high_seq: Bytes,
+}
+
+impl WindowDctcp {
+    /// Creates a new window-based DCTCP instance with gain `gain`.
+    pub fn new(init: CcInit, gain: f64, pacing: bool) -> Self {
+        let max_cwnd = init.window.into_f64();
+        let cwnd = if init.rate < init.max_rate {
+            init.rate.width(init.base_rtt).into_f64().min(max_cwnd)
+        } else {
+            max_cwnd
+        };
+        Self {
+            max_rate: init.max_rate,
+            sz_pktmax: init.sz_pktmax.into_f64(),
+            max_cwnd,
+            pacing,
+            cwnd,
+            srtt: init.base_rtt,
+            estimator: AlphaEstimator::new(gain),
+            ca_state: CaState::default(),
+            high_seq: Bytes::ZERO,
+        }
+    }
+
+    // The window never shrinks below two packets, as in Linux
+    fn clamp_cwnd(&mut self) {
+        let min_cwnd = (2.0 * self.sz_pktmax).min(self.max_cwnd);
+        self.cwnd = self.cwnd.clamp(min_cwnd, self.max_cwnd);
+    }
+}
+
+impl CongestionControl for WindowDctcp {
+    fn rate(&self) -> BitsPerSec {
+        if !self.pacing {
+            return self.max_rate;
+        }
+        let srtt = cmp::max(self.srtt, Nanosecs::ONE).into_f64();
+        let rate = BitsPerSec::new((PACING_GAIN * self.cwnd * 8e9 / srtt).round() as u64);
+        cmp::max(cmp::min(rate, self.max_rate), BitsPerSec::ONE)
+    }
+
+    fn window(&self) -> Bytes {
+        Bytes::new(self.cwnd.round() as u64)
+    }
+
+    fn on_loss(&mut self, _now: Time) {
+        self.cwnd /= 2.0;
+        self.clamp_cwnd();
+    }
+
+    fn on_ack(&mut self, ack: &AckInfo) {
+        self.srtt = Nanosecs::new(
+            (0.875 * self.srtt.into_f64() + 0.125 * ack.rtt.into_f64()).round() as u64,
+        );
+        self.estimator.on_ack(ack);
+        if self.ca_state == CaState::One && ack.snd_una > self.high_seq {
+            self.ca_state = CaState::Zero;
+        }
+        if self.ca_state == CaState::Zero {
+            if ack.marked {
+                // Reduce the window once per window of data
+                self.cwnd *= 1.0 - self.estimator.alpha / 2.0;
+                self.ca_state = CaState::One;
+                self.high_seq = ack.snd_nxt;
+            } else {
+                // Congestion avoidance
+                self.cwnd += self.sz_pktmax * ack.nr_bytes.into_f64() / self.cwnd;
+            }
+        }
+        self.clamp_cwnd();
+    }
+}
+
+// Estimates the fraction of marked packets once per window of data.
+#[derive(Debug, Clone)]
+struct AlphaEstimator {
+    alpha: f64,
+    gain: f64,
+    last_update_seq: Bytes,
+    batch_size: usize,
+    marked_count: usize,
+}
+
+impl AlphaEstimator {
+    fn new(gain: f64) -> Self {
+        Self {
+            alpha: 1.0,
+            gain,
+            last_update_seq: Bytes::ZERO,
+            batch_size: 0,
+            marked_count: 0,
+        }
+    }
+
+    // Returns whether the ACK starts a new window of data.
+    fn on_ack(&mut self, ack: &AckInfo) -> bool {
+        // A coalesced ACK may echo the marks of several packets
+        self.marked_count += ack
+            .marked_bytes
+            .into_usize()
+            .div_ceil(ack.sz_pktmax.into_usize());
+        if ack.snd_una <= self.last_update_seq {
+            return false;
+        }
+        if self.last_update_seq == Bytes::ZERO {
+            // First RTT
+            self.batch_size = Packet::min_count_in(ack.snd_nxt, ack.sz_pktmax);
+        } else {
+            // The previous window may have been empty if all data was acknowledged
+            let batch_size = cmp::max(self.batch_size, 1);
+            let frac = (self.marked_count as f64 / batch_size as f64).clamp(0.0, 1.0);
+            self.alpha = (1.0 - self.gain) * self.alpha + self.gain * frac;
+            self.marked_count = 0;
+            self.batch_size = Packet::min_count_in(ack.snd_nxt - ack.snd_una, ack.sz_pktmax);
+        }
+        self.last_update_seq = ack.snd_nxt;
+        true
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, derivative::Derivative)]
+#[derivative(Default)]
+enum CaState {
+    #[derivative(Default)]
+    Zero,
+    One,
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::units::{Gbps, Mbps, Microsecs};
+
+    use super::*;
+
+    fn mk_window_dctcp(pacing: bool) -> WindowDctcp {
+        let init = CcInit {
+            now: Time::ZERO,
+            max_rate: Gbps::new(10).into_bps(),
+            rate: Gbps::new(10).into_bps(),
+            window: Bytes::new(100_000),
+            base_rtt: Nanosecs::new(4_000),
+            nr_hops: 1,
+            sz_pktmax: Bytes::new(1_000),
+            size: Bytes::new(1_000_000),
+            deadline: None,
+        };
+        let mut cc = WindowDctcp::new(init, 0.0625, pacing);
+        cc.cwnd = 10_000.0;
+        cc
+    }
+
+    fn d2tcp_rate_after_mark(deadline: Option<Microsecs>) -> BitsPerSec {
+        let init = CcInit {
+            now: Time::ZERO,
+            max_rate: Gbps::new(10).into_bps(),
+            rate: Gbps::new(10).into_bps(),
+            window: Bytes::new(100_000),
+            base_rtt: Nanosecs::new(4_000),
+            nr_hops: 1,
+            sz_pktmax: Bytes::new(1_000),
+            size: Bytes::new(1_000_000),
+            deadline: deadline.map(|d| d.into_ns().into_time()),
+        };
+        let mut cc = Dctcp::d2tcp(init, 0.0625, Mbps::new(615).into_bps());
+        cc.estimator.alpha = 0.25;
+        cc.on_ack(&mk_ack(1_000, 10_000, true));
+        cc.rate()
+    }
+
+    fn mk_ack(snd_una: u64, snd_nxt: u64, marked: bool) -> AckInfo<'static> {
+        AckInfo {
+            now: Time::ZERO,
+            nr_bytes: Bytes::new(1_000),
+            marked,
+            marked_bytes: if marked {
+                Bytes::new(1_000)
+            } else {
+                Bytes::ZERO
+            },
+            rtt: Nanosecs::new(4_000),
+            snd_una: Bytes::new(snd_una),
+            snd_nxt: Bytes::new(snd_nxt),
+            sz_pktmax: Bytes::new(1_000),
+            int: &[],
+        }
+    }
+
+    #[test]
+    fn marks_cut_cwnd_once_per_window() {
+        let mut cc = mk_window_dctcp(false);
+        cc.on_ack(&mk_ack(1_000, 10_000, true));
+        // alpha starts at 1, so the first cut halves the window
+        assert_eq!(cc.window(), Bytes::new(5_000));
+        cc.on_ack(&mk_ack(2_000, 10_000, true));
+        assert_eq!(cc.window(), Bytes::new(5_000));
+        cc.on_ack(&mk_ack(11_000, 15_000, true));
+        assert!(cc.window() < Bytes::new(5_000));
+    }
+
+    #[test]
+    fn cwnd_grows_by_a_packet_per_window() {
+        let mut cc = mk_window_dctcp(false);
+        for i in 1..=10 {
+            cc.on_ack(&mk_ack(i * 1_000, 10_000, false));
+        }
+        let window = cc.window().into_u64();
+        assert!((10_900..=11_000).contains(&window));
+        assert_eq!(cc.rate(), Gbps::new(10).into_bps());
+    }
+
+    #[test]
+    fn pacing_spreads_cwnd_over_srtt() {
+        let mut cc = mk_window_dctcp(true);
+        // Pacing never exceeds the maximum rate
+        assert_eq!(cc.rate(), Gbps::new(10).into_bps());
+        // 1.2 * 2 KB per 4 us
+        cc.cwnd = 2_000.0;
+        assert_eq!(cc.rate(), Mbps::new(4_800).into_bps());
+    }
+
+    #[test]
+    fn d2tcp_backs_off_less_near_deadlines() {
+        // The flow needs about 1 ms to complete at 10 Gbps
+        let far = d2tcp_rate_after_mark(Some(Microsecs::new(10_000)));
+        let near = d2tcp_rate_after_mark(Some(Microsecs::new(500)));
+        let none = d2tcp_rate_after_mark(None);
+        assert!(far < none);
+        assert!(none < near);
+    }
+}
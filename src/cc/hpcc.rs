@@ -0,0 +1,259 @@
+use std::cmp;
+
+use crate::{
+    packet::IntHop,
+    units::{BitsPerSec, Bytes, Mbps, Nanosecs},
+};
+
+use super::{AckInfo, CcInit, CongestionControl};
+
+/// HPCC parameters.
+#[derive(Debug, Clone, Copy, typed_builder::TypedBuilder, serde::Serialize, serde::Deserialize)]
+pub struct HpccConfig {
+    /// The target link utilization (η).
+    #[builder(default = 0.95)]
+    pub target_util: f64,
+    /// The number of consecutive additive increases after which the window is recomputed from
+    /// the measured utilization (`maxStage`).
+    #[builder(default = 5)]
+    pub max_stage: usize,
+    /// The additive increase step, expressed as a rate. The window grows by the number of bytes
+    /// this rate sends in one base RTT.
+    #[builder(default = Mbps::new(50).into_bps(), setter(into))]
+    pub additive_inc: BitsPerSec,
+    /// The minimum sending rate.
+    #[builder(default = Mbps::new(100).into_bps(), setter(into))]
+    pub min_rate: BitsPerSec,
+}
+
+impl Default for HpccConfig {
+    fn default() -> Self {
+        Self::builder().build()
+    }
+}
+
+/// HPCC, as described in "HPCC: High Precision Congestion Control" (SIGCOMM '19).
+///
+/// The window is computed from the link utilization reported by the in-band network telemetry
+/// echoed on every ACK, using the most utilized link on the flow's path. The flow is paced at one
+/// window per base RTT.
+#[derive(Debug, Clone)]
+pub struct Hpcc {
+    cfg: HpccConfig,
+    base_rtt: Nanosecs,
+    max_rate: BitsPerSec,
+    min_window: f64,
+    max_window: f64,
+    w_ai: f64,
+
+    // Reference and current windows, in bytes
+    wc: f64,
+    w: f64,
+    inc_stage: usize,
+    last_update_seq: Bytes,
+
+    // Normalized in-flight bytes, smoothed over one base RTT
+    util: f64,
+    // The telemetry of each hop on the previous ACK
+    prev_int: Vec<IntHop>,
+}
+
+impl Hpcc {
+    /// Creates a new HPCC instance.
+    pub fn new(init: CcInit, cfg: HpccConfig) -> Self {
+        let base_rtt = cmp::max(init.base_rtt, Nanosecs::ONE);
+        let max_window = init.window.into_f64();
+        let w = if init.rate < init.max_rate {
+            init.rate.width(base_rtt).into_f64().min(max_window)
+        } else {
+            max_window
+        };
+        Self {
+            cfg,
+            base_rtt,
+            max_rate: init.max_rate,
+            min_window: cfg.min_rate.width(base_rtt).into_f64().max(1.0),
+            max_window,
+            w_ai: cfg.additive_inc.width(base_rtt).into_f64(),
+            wc: w,
+            w,
+            inc_stage: 0,
+            last_update_seq: Bytes::ZERO,
+            util: 0.0,
+            prev_int: Vec::new(),
+        }
+    }
+
+    // Returns the updated utilization estimate, or `None` if the telemetry cannot be compared
+    // with the previous sample.
+    fn measure_inflight(&self, int: &[IntHop]) -> Option<f64> {
+        if int.len() != self.prev_int.len() {
+            return None;
+        }
+        let base_rtt = self.base_rtt.into_f64();
+        // The utilization of the most utilized hop, along with its sampling interval
+        let (u, tau) = int
+            .iter()
+            .zip(&self.prev_int)
+            .filter_map(|(cur, prev)| {
+                // Utilization is undefined while the link is down
+                if cur.ts <= prev.ts || cur.bandwidth == BitsPerSec::ZERO {
+                    return None;
+                }
+                let tau = (cur.ts - prev.ts).into_f64();
+                // Bandwidth in bytes per nanosecond
+                let bw = cur.bandwidth.into_f64() / 8e9;
+                let tx_rate = (cur.tx_bytes - prev.tx_bytes).into_f64() / tau;
+                let qlen = cmp::min(cur.qlen, prev.qlen).into_f64();
+                Some((qlen / (bw * base_rtt) + tx_rate / bw, tau))
+            })
+            .max_by(|a, b| a.0.total_cmp(&b.0))?;
+        let tau = tau.min(base_rtt);
+        Some((1.0 - tau / base_rtt) * self.util + tau / base_rtt * u)
+    }
+
+    fn compute_window(&mut self, update_wc: bool) {
+        let eta = self.cfg.target_util;
+        let w = if self.util >= eta || self.inc_stage >= self.cfg.max_stage {
+            let w = self.wc / (self.util / eta) + self.w_ai;
+            if update_wc {
+                self.inc_stage = 0;
+            }
+            w
+        } else {
+            if update_wc {
+                self.inc_stage += 1;
+            }
+            self.wc + self.w_ai
+        };
+        self.w = w.clamp(self.min_window, self.max_window);
+        if update_wc {
+            self.wc = self.w;
+        }
+    }
+}
+
+impl CongestionControl for Hpcc {
+    fn rate(&self) -> BitsPerSec {
+        let rate = BitsPerSec::new((self.w * 8e9 / self.base_rtt.into_f64()).round() as u64);
+        cmp::min(rate, self.max_rate)
+    }
+
+    fn window(&self) -> Bytes {
+        Bytes::new(self.w.round() as u64)
+    }
+
+    fn on_ack(&mut self, ack: &AckInfo) {
+        if let Some(util) = self.measure_inflight(ack.int) {
+            self.util = util;
+            let update_wc = ack.snd_una > self.last_update_seq;
+            self.compute_window(update_wc);
+            if update_wc {
+                self.last_update_seq = ack.snd_nxt;
+            }
+        } else if self.prev_int.is_empty() {
+            self.last_update_seq = ack.snd_nxt;
+        }
+        self.prev_int.clear();
+        self.prev_int.extend_from_slice(ack.int);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{time::Time, units::Gbps};
+
+    use super::*;
+
+    fn mk_hpcc() -> Hpcc {
+        let init = CcInit {
+            now: Time::ZERO,
+            max_rate: Gbps::new(10).into_bps(),
+            rate: Gbps::new(10).into_bps(),
+            window: Bytes::new(100_000),
+            base_rtt: Nanosecs::new(4_000),
+            nr_hops: 1,
+            sz_pktmax: Bytes::new(1_000),
+            size: Bytes::new(1_000_000),
+            deadline: None,
+        };
+        Hpcc::new(init, HpccConfig::default())
+    }
+
+    // The `i`th telemetry sample of a link stamped once every base RTT, during which the link sent
+    // `tx_bytes` bytes and held `qlen` bytes.
+    fn mk_int(i: u64, tx_bytes: u64, qlen: u64) -> IntHop {
+        IntHop {
+            qlen: Bytes::new(qlen),
+            tx_bytes: Bytes::new(i * tx_bytes),
+            ts: Time::new(u128::from(i) * 4_000),
+            bandwidth: Gbps::new(10).into_bps(),
+        }
+    }
+
+    // The `i`th ACK of a stream spaced one base RTT apart, carrying the samples `int`. Every ACK
+    // starts a new window of data.
+    fn mk_ack(i: u64, int: &[IntHop]) -> AckInfo<'_> {
+        AckInfo {
+            now: Time::new(u128::from(i) * 4_000),
+            nr_bytes: Bytes::new(1_000),
+            marked: false,
+            marked_bytes: Bytes::ZERO,
+            rtt: Nanosecs::new(4_000),
+            snd_una: Bytes::new(i * 1_000),
+            snd_nxt: Bytes::new(i * 1_000),
+            sz_pktmax: Bytes::new(1_000),
+            int,
+        }
+    }
+
+    #[test]
+    fn window_shrinks_above_target_utilization() {
+        let mut cc = mk_hpcc();
+        let w = cc.w;
+        // The link runs at line rate with two BDPs of queue, so U = 3
+        cc.on_ack(&mk_ack(1, &[mk_int(1, 5_000, 10_000)]));
+        cc.on_ack(&mk_ack(2, &[mk_int(2, 5_000, 10_000)]));
+        assert!((cc.util - 3.0).abs() < 1e-9);
+        let expected = w / (3.0 / cc.cfg.target_util) + cc.w_ai;
+        assert!((cc.w - expected).abs() < 1e-6);
+        assert!(cc.window() < Bytes::new(w as u64));
+    }
+
+    #[test]
+    fn additive_increase_stops_after_max_stage() {
+        // A 500 B window on a half-utilized link
+        let mut cc = mk_hpcc();
+        cc.w = 500.0;
+        cc.wc = 500.0;
+        let max_stage = cc.cfg.max_stage;
+        cc.on_ack(&mk_ack(1, &[mk_int(1, 2_500, 0)]));
+        let mut windows = vec![cc.w];
+        for i in 2..=max_stage as u64 + 2 {
+            cc.on_ack(&mk_ack(i, &[mk_int(i, 2_500, 0)]));
+            windows.push(cc.w);
+        }
+        let steps = windows.windows(2).map(|w| w[1] - w[0]).collect::<Vec<_>>();
+        assert!(steps[..max_stage]
+            .iter()
+            .all(|&step| (step - cc.w_ai).abs() < 1e-6));
+        // The window is then recomputed from the measured utilization
+        let last = windows[max_stage];
+        let expected = last / (0.5 / cc.cfg.target_util) + cc.w_ai;
+        assert!((windows[max_stage + 1] - expected).abs() < 1e-6);
+        assert_eq!(cc.inc_stage, 0);
+    }
+
+    #[test]
+    fn most_utilized_hop_sets_the_window() {
+        let mut cc = mk_hpcc();
+        // The first hop runs at line rate with two BDPs of queue, and the second is half idle
+        for i in 1..=2 {
+            cc.on_ack(&mk_ack(i, &[mk_int(i, 5_000, 10_000), mk_int(i, 2_500, 0)]));
+        }
+        assert!((cc.util - 3.0).abs() < 1e-9);
+        // Telemetry from a path of a different length is not compared
+        cc.on_ack(&mk_ack(3, &[mk_int(3, 2_500, 0)]));
+        assert!((cc.util - 3.0).abs() < 1e-9);
+    }
+}
@@ -0,0 +1,279 @@
+use std::cmp;
+
+use crate::{
+    time::Time,
+    units::{BitsPerSec, Bytes, Microsecs, Nanosecs},
+};
+
+use super::{AckInfo, CcInit, CongestionControl};
+
+/// Swift parameters.
+#[derive(Debug, Clone, Copy, typed_builder::TypedBuilder, serde::Serialize, serde::Deserialize)]
+pub struct SwiftConfig {
+    /// The base target delay of a flow crossing a single bottleneck link.
+    #[builder(default = Microsecs::new(25).into_ns(), setter(into))]
+    pub base_target: Nanosecs,
+    /// The extra target delay per additional bottleneck link on a flow's path (topology
+    /// scaling).
+    #[builder(default = Microsecs::new(1).into_ns(), setter(into))]
+    pub per_hop_target: Nanosecs,
+    /// The maximum extra target delay granted to flows with small windows (flow scaling).
+    #[builder(default = Microsecs::new(10).into_ns(), setter(into))]
+    pub fs_range: Nanosecs,
+    /// The window, in packets, at which flow scaling grants the full `fs_range`.
+    #[builder(default = 0.1)]
+    pub fs_min_cwnd: f64,
+    /// The window, in packets, at which flow scaling stops granting extra delay.
+    #[builder(default = 100.0)]
+    pub fs_max_cwnd: f64,
+    /// The additive increase, in packets per RTT.
+    #[builder(default = 1.0)]
+    pub additive_inc: f64,
+    /// The multiplicative decrease factor.
+    #[builder(default = 0.8)]
+    pub beta: f64,
+    /// The maximum multiplicative decrease applied at once.
+    #[builder(default = 0.5)]
+    pub max_mdf: f64,
+    /// The minimum window, in packets. Windows below one packet are enforced by pacing.
+    #[builder(default = 0.01)]
+    pub min_cwnd: f64,
+}
+
+impl Default for SwiftConfig {
+    fn default() -> Self {
+        Self::builder().build()
+    }
+}
+
+/// Swift, as described in "Swift: Delay is Simple and Effective for Congestion Control in the
+/// Datacenter" (SIGCOMM '20).
+///
+/// The window is increased additively while the measured RTT stays below the target delay and
+/// decreased multiplicatively, at most once per RTT, otherwise.
+#[derive(Debug, Clone)]
+pub struct Swift {
+    cfg: SwiftConfig,
+    max_rate: BitsPerSec,
+    sz_pktmax: f64,
+    max_cwnd: f64,
+
+    // Congestion window, in bytes
+    cwnd: f64,
+    srtt: Nanosecs,
+    last_decrease: Option<Time>,
+
+    // Flow scaling coefficients
+    fs_alpha: f64,
+    fs_beta: f64,
+    // The target delay before flow scaling, including topology scaling
+    hop_target: f64,
+}
+
+impl Swift {
+    /// Creates a new Swift instance.
+    pub fn new(init: CcInit, cfg: SwiftConfig) -> Self {
+        let fs_range = cfg.fs_range.into_f64();
+        let fs_alpha = fs_range / (1.0 / cfg.fs_min_cwnd.sqrt() - 1.0 / cfg.fs_max_cwnd.sqrt());
+        let fs_beta = -fs_alpha / cfg.fs_max_cwnd.sqrt();
+        let nr_extra_hops = init.nr_hops.saturating_sub(1) as f64;
+        let hop_target = cfg.base_target.into_f64() + nr_extra_hops * cfg.per_hop_target.into_f64();
+        Self {
+            cfg,
+            max_rate: init.max_rate,
+            sz_pktmax: init.sz_pktmax.into_f64(),
+            max_cwnd: init.window.into_f64(),
+            cwnd: if init.rate < init.max_rate {
+                init.rate
+                    .width(init.base_rtt)
+                    .into_f64()
+                    .min(init.window.into_f64())
+            } else {
+                init.window.into_f64()
+            },
+            srtt: init.base_rtt,
+            last_decrease: None,
+            fs_alpha,
+            fs_beta,
+            hop_target,
+        }
+    }
+
+    // The target delay, scaled by the window (flow scaling) and the path length (topology
+    // scaling)
+    fn target_delay(&self) -> f64 {
+        let cwnd_pkts = self.cwnd / self.sz_pktmax;
+        let fs = (self.fs_alpha / cwnd_pkts.sqrt() + self.fs_beta)
+            .clamp(0.0, self.cfg.fs_range.into_f64());
+        self.hop_target + fs
+    }
+
+    // The window is decreased at most once per RTT
+    fn can_decrease(&self, now: Time) -> bool {
+        self.last_decrease
+            .is_none_or(|t| now - t >= self.srtt.into_delta())
+    }
+
+    fn clamp_window(&mut self) {
+        let min_cwnd = self.cfg.min_cwnd * self.sz_pktmax;
+        self.cwnd = self.cwnd.clamp(min_cwnd, self.max_cwnd.max(min_cwnd));
+    }
+}
+
+impl CongestionControl for Swift {
+    fn rate(&self) -> BitsPerSec {
+        if self.cwnd >= self.sz_pktmax {
+            self.max_rate
+        } else {
+            // Sub-packet windows are enforced by pacing one window per RTT
+            let srtt = cmp::max(self.srtt, Nanosecs::ONE).into_f64();
+            let rate = BitsPerSec::new((self.cwnd * 8e9 / srtt).round() as u64);
+            cmp::max(cmp::min(rate, self.max_rate), BitsPerSec::ONE)
+        }
+    }
+
+    fn window(&self) -> Bytes {
+        // A sub-packet window still allows a full-size packet, which `rate` spaces out so that
+        // only the fraction of a packet is sent per RTT on average
+        Bytes::new(self.cwnd.max(self.sz_pktmax).round() as u64)
+    }
+
+    fn on_ack(&mut self, ack: &AckInfo) {
+        self.srtt = Nanosecs::new(
+            (0.875 * self.srtt.into_f64() + 0.125 * ack.rtt.into_f64()).round() as u64,
+        );
+        let delay = ack.rtt.into_f64();
+        let target = self.target_delay();
+        let acked_pkts = ack.nr_bytes.into_f64() / self.sz_pktmax;
+        if delay < target {
+            let ai = self.cfg.additive_inc * self.sz_pktmax;
+            if self.cwnd >= self.sz_pktmax {
+                self.cwnd += ai * acked_pkts * self.sz_pktmax / self.cwnd;
+            } else {
+                self.cwnd += ai * acked_pkts;
+            }
+        } else if self.can_decrease(ack.now) {
+            let mdf = (1.0 - self.cfg.beta * (delay - target) / delay).max(1.0 - self.cfg.max_mdf);
+            self.cwnd *= mdf;
+            self.last_decrease = Some(ack.now);
+        }
+        self.clamp_window();
+    }
+
+    fn on_loss(&mut self, now: Time) {
+        // Losses cut the window by the maximum decrease, subject to the same once-per-RTT limit
+        if self.can_decrease(now) {
+            self.cwnd *= 1.0 - self.cfg.max_mdf;
+            self.last_decrease = Some(now);
+            self.clamp_window();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::units::Gbps;
+
+    use super::*;
+
+    fn mk_swift(nr_hops: usize) -> Swift {
+        let init = CcInit {
+            now: Time::ZERO,
+            max_rate: Gbps::new(10).into_bps(),
+            rate: Gbps::new(10).into_bps(),
+            window: Bytes::new(200_000),
+            base_rtt: Nanosecs::new(4_000),
+            nr_hops,
+            sz_pktmax: Bytes::new(1_000),
+            size: Bytes::new(1_000_000),
+            deadline: None,
+        };
+        Swift::new(init, SwiftConfig::default())
+    }
+
+    fn mk_ack(now: Time, rtt: Nanosecs) -> AckInfo<'static> {
+        AckInfo {
+            now,
+            nr_bytes: Bytes::new(1_000),
+            marked: false,
+            marked_bytes: Bytes::ZERO,
+            rtt,
+            snd_una: Bytes::ZERO,
+            snd_nxt: Bytes::ZERO,
+            sz_pktmax: Bytes::new(1_000),
+            int: &[],
+        }
+    }
+
+    #[test]
+    fn target_delay_scales_with_window_and_hops() {
+        let cfg = SwiftConfig::default();
+        let base = cfg.base_target.into_f64();
+        let mut cc = mk_swift(1);
+        // Flows with large windows get no extra delay
+        cc.cwnd = 100.0 * 1_000.0;
+        assert!((cc.target_delay() - base).abs() < 1e-6);
+        // Flows with tiny windows get the full flow-scaling range
+        cc.cwnd = 0.1 * 1_000.0;
+        assert!((cc.target_delay() - base - cfg.fs_range.into_f64()).abs() < 1e-6);
+        // In between, smaller windows get more extra delay
+        cc.cwnd = 4_000.0;
+        let small = cc.target_delay();
+        cc.cwnd = 16_000.0;
+        assert!(small > cc.target_delay());
+        // Every additional hop adds to the target
+        let mut cc = mk_swift(3);
+        cc.cwnd = 100.0 * 1_000.0;
+        let expected = base + 2.0 * cfg.per_hop_target.into_f64();
+        assert!((cc.target_delay() - expected).abs() < 1e-6);
+    }
+
+    #[test]
+    fn decrease_is_capped_and_once_per_rtt() {
+        let mut cc = mk_swift(1);
+        cc.cwnd = 100_000.0;
+        // An RTT ten times the target would cut the window by 72%, but the cut is capped at 50%
+        let rtt = Microsecs::new(250).into_ns();
+        cc.on_ack(&mk_ack(Time::ZERO, rtt));
+        assert_eq!(cc.window(), Bytes::new(50_000));
+        // No further cut within the same RTT
+        cc.on_ack(&mk_ack(Time::new(1_000), rtt));
+        assert_eq!(cc.window(), Bytes::new(50_000));
+        // The next RTT allows another cut
+        cc.on_ack(&mk_ack(Time::ZERO + rtt.into_delta(), rtt));
+        assert_eq!(cc.window(), Bytes::new(25_000));
+    }
+
+    #[test]
+    fn loss_cuts_window_once_per_rtt() {
+        let mut cc = mk_swift(1);
+        cc.cwnd = 100_000.0;
+        cc.on_loss(Time::ZERO);
+        assert_eq!(cc.window(), Bytes::new(50_000));
+        // Losses detected within the same RTT don't cut the window again
+        cc.on_loss(Time::new(1_000));
+        assert_eq!(cc.window(), Bytes::new(50_000));
+        cc.on_loss(Time::new(4_000));
+        assert_eq!(cc.window(), Bytes::new(25_000));
+    }
+
+    #[test]
+    fn sub_packet_window_sends_full_packets() {
+        let mut cc = mk_swift(1);
+        assert_eq!(cc.rate(), Gbps::new(10).into_bps());
+        // Half a packet per 4 us: the window still admits a full-size packet, which the rate
+        // spaces out over two RTTs
+        cc.cwnd = 500.0;
+        assert_eq!(cc.window(), Bytes::new(1_000));
+        assert_eq!(cc.rate(), Gbps::new(1).into_bps());
+        assert_eq!(cc.rate().length(Bytes::new(1_000)), Nanosecs::new(8_000));
+        // Below one packet, the window grows by the additive increase per packet acknowledged
+        cc.on_ack(&mk_ack(Time::ZERO, Nanosecs::new(4_000)));
+        assert_eq!(cc.window(), Bytes::new(1_500));
+        // The window never drops below the minimum
+        cc.cwnd = 1.0;
+        cc.on_ack(&mk_ack(Time::ZERO, Microsecs::new(250).into_ns()));
+        assert_eq!(cc.cwnd, 10.0);
+        assert_eq!(cc.window(), Bytes::new(1_000));
+    }
+}
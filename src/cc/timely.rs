@@ -0,0 +1,204 @@
+use std::cmp;
+
+use crate::units::{BitsPerSec, Bytes, Mbps, Microsecs, Nanosecs};
+
+use super::{AckInfo, CcInit, CongestionControl};
+
+/// TIMELY parameters.
+#[derive(Debug, Clone, Copy, typed_builder::TypedBuilder, serde::Serialize, serde::Deserialize)]
+pub struct TimelyConfig {
+    /// The EWMA weight given to new RTT differences.
+    #[builder(default = 0.875)]
+    pub alpha: f64,
+    /// The multiplicative decrease factor.
+    #[builder(default = 0.8)]
+    pub beta: f64,
+    /// The additive increase step.
+    #[builder(default = Mbps::new(10).into_bps(), setter(into))]
+    pub additive_inc: BitsPerSec,
+    /// The RTT below which the rate is always increased.
+    #[builder(default = Microsecs::new(50).into_ns(), setter(into))]
+    pub t_low: Nanosecs,
+    /// The RTT above which the rate is always decreased.
+    #[builder(default = Microsecs::new(500).into_ns(), setter(into))]
+    pub t_high: Nanosecs,
+    /// The RTT used to normalize the RTT gradient.
+    #[builder(default = Microsecs::new(20).into_ns(), setter(into))]
+    pub min_rtt: Nanosecs,
+    /// The number of consecutive increases after which hyperactive increase kicks in.
+    #[builder(default = 5)]
+    pub hai_threshold: usize,
+    /// The minimum sending rate.
+    #[builder(default = Mbps::new(100).into_bps(), setter(into))]
+    pub min_rate: BitsPerSec,
+}
+
+impl Default for TimelyConfig {
+    fn default() -> Self {
+        Self::builder().build()
+    }
+}
+
+/// TIMELY, as described in "TIMELY: RTT-based Congestion Control for the Datacenter"
+/// (SIGCOMM '15).
+///
+/// The rate is updated once per RTT based on the gradient of the measured RTT.
+#[derive(Debug, Clone)]
+pub struct Timely {
+    cfg: TimelyConfig,
+    rate: BitsPerSec,
+    max_rate: BitsPerSec,
+    window: Bytes,
+
+    prev_rtt: Option<Nanosecs>,
+    rtt_diff: f64,
+    nr_increases: usize,
+    last_update_seq: Bytes,
+}
+
+impl Timely {
+    /// Creates a new TIMELY instance.
+    pub fn new(init: CcInit, cfg: TimelyConfig) -> Self {
+        Self {
+            cfg,
+            rate: init.rate,
+            max_rate: init.max_rate,
+            window: init.window,
+            prev_rtt: None,
+            rtt_diff: 0.0,
+            nr_increases: 0,
+            last_update_seq: Bytes::ZERO,
+        }
+    }
+
+    fn update_rate(&mut self, rtt: Nanosecs) {
+        let Some(prev_rtt) = self.prev_rtt.replace(rtt) else {
+            return;
+        };
+        let new_rtt_diff = rtt.into_f64() - prev_rtt.into_f64();
+        self.rtt_diff = (1.0 - self.cfg.alpha) * self.rtt_diff + self.cfg.alpha * new_rtt_diff;
+        let gradient = self.rtt_diff / cmp::max(self.cfg.min_rtt, Nanosecs::ONE).into_f64();
+
+        let new_rate = if rtt < self.cfg.t_low {
+            self.nr_increases = 0;
+            self.rate.saturating_add(self.cfg.additive_inc)
+        } else if rtt > self.cfg.t_high {
+            self.nr_increases = 0;
+            let frac = 1.0 - Nanosecs::frac(self.cfg.t_high, rtt);
+            self.rate.scale_by(1.0 - self.cfg.beta * frac)
+        } else if gradient <= 0.0 {
+            self.nr_increases += 1;
+            let n = if self.nr_increases >= self.cfg.hai_threshold {
+                self.cfg.hai_threshold
+            } else {
+                1
+            };
+            self.rate
+                .saturating_add(self.cfg.additive_inc.scale_by(n as f64))
+        } else {
+            self.nr_increases = 0;
+            self.rate
+                .scale_by((1.0 - self.cfg.beta * gradient).max(0.0))
+        };
+        self.rate = cmp::min(self.max_rate, cmp::max(self.cfg.min_rate, new_rate));
+    }
+}
+
+impl CongestionControl for Timely {
+    fn rate(&self) -> BitsPerSec {
+        self.rate
+    }
+
+    fn window(&self) -> Bytes {
+        self.window
+    }
+
+    fn on_ack(&mut self, ack: &AckInfo) {
+        if ack.snd_una > self.last_update_seq {
+            self.update_rate(ack.rtt);
+            self.last_update_seq = ack.snd_nxt;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{time::Time, units::Gbps};
+
+    use super::*;
+
+    fn mk_timely() -> Timely {
+        let init = CcInit {
+            now: Time::ZERO,
+            max_rate: Gbps::new(10).into_bps(),
+            rate: Gbps::new(5).into_bps(),
+            window: Bytes::new(100_000),
+            base_rtt: Nanosecs::new(4_000),
+            nr_hops: 1,
+            sz_pktmax: Bytes::new(1_000),
+            size: Bytes::new(1_000_000),
+            deadline: None,
+        };
+        let mut cc = Timely::new(init, TimelyConfig::default());
+        // Start below line rate, so that the rate can grow
+        cc.rate = Gbps::new(5).into_bps();
+        cc
+    }
+
+    // Feeds one RTT sample per window of data.
+    fn feed(cc: &mut Timely, rtts_us: &[u64]) {
+        for &rtt in rtts_us {
+            let seq = cc.last_update_seq + Bytes::new(1_000);
+            cc.on_ack(&AckInfo {
+                now: Time::ZERO,
+                nr_bytes: Bytes::new(1_000),
+                marked: false,
+                marked_bytes: Bytes::ZERO,
+                rtt: Microsecs::new(rtt).into_ns(),
+                snd_una: seq,
+                snd_nxt: seq,
+                sz_pktmax: Bytes::new(1_000),
+                int: &[],
+            });
+        }
+    }
+
+    #[test]
+    fn rtt_above_t_high_cuts_rate() {
+        let mut cc = mk_timely();
+        // The cut is beta * (1 - t_high / rtt) = 0.8 * 0.5
+        feed(&mut cc, &[100, 1_000]);
+        assert_eq!(cc.rate(), Gbps::new(3).into_bps());
+    }
+
+    #[test]
+    fn positive_gradient_cuts_rate() {
+        let mut cc = mk_timely();
+        // The RTT difference is smoothed to 8.75 us, a gradient of 0.4375 min RTTs
+        feed(&mut cc, &[100, 110]);
+        assert_eq!(cc.rate(), Mbps::new(3_250).into_bps());
+    }
+
+    #[test]
+    fn hyperactive_increase_after_consecutive_negative_gradients() {
+        let mut cc = mk_timely();
+        let cfg = TimelyConfig::default();
+        feed(&mut cc, &[200]);
+        let mut rate = cc.rate();
+        for i in 1..=cfg.hai_threshold + 1 {
+            feed(&mut cc, &[200 - i as u64]);
+            let n = if i >= cfg.hai_threshold {
+                cfg.hai_threshold
+            } else {
+                1
+            };
+            let step = cfg.additive_inc.scale_by(n as f64);
+            assert_eq!(cc.rate(), rate + step);
+            rate = cc.rate();
+        }
+        // A positive gradient resets the count
+        feed(&mut cc, &[250]);
+        assert!(cc.rate() < rate);
+        assert_eq!(cc.nr_increases, 0);
+    }
+}
@@ -1,11 +1,79 @@
 use std::cmp::Ordering;
 
 use crate::{
+    flow::MAX_MLFQ_LEVELS,
     port::QIndex,
     units::{Bytes, Nanosecs},
-    FlowId,
+    BackgroundId, CoflowId, FlowId, LinkId, SourceId,
 };
 
+/// The output of a simulation.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct Output {
+    /// The flow completion time records.
+    pub records: Vec<Record>,
+    /// The statistics of each queue of the top-level bottleneck.
+    pub queues: Vec<QueueStats>,
+    /// The statistics of each bottleneck link, sorted by link ID.
+    #[serde(default)]
+    pub links: Vec<LinkStats>,
+    /// The statistics of each source, sorted by source ID.
+    pub sources: Vec<SourceStats>,
+    /// The statistics of each background stream, sorted by stream ID.
+    #[serde(default)]
+    pub background: Vec<BackgroundStats>,
+    /// The coflow completion time records, sorted by coflow ID. Coflows with incomplete flows
+    /// are omitted.
+    #[serde(default)]
+    pub coflows: Vec<CoflowRecord>,
+}
+
+/// Bottleneck queue statistics.
+#[derive(Debug, Clone, Copy, serde::Serialize, serde::Deserialize)]
+pub struct QueueStats {
+    /// The queue index.
+    pub qindex: QIndex,
+    /// The largest queue size observed.
+    pub max_occupancy: Bytes,
+    /// The time-averaged queue size.
+    pub mean_occupancy: Bytes,
+    /// The number of packets dropped at admission.
+    pub drops: usize,
+}
+
+/// Bottleneck link statistics.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct LinkStats {
+    /// The link ID.
+    pub id: LinkId,
+    /// The statistics of each queue.
+    pub queues: Vec<QueueStats>,
+}
+
+/// Background traffic statistics. Background bytes are not part of any flow's record.
+#[derive(Debug, Clone, Copy, serde::Serialize, serde::Deserialize)]
+pub struct BackgroundStats {
+    /// The stream ID.
+    pub id: BackgroundId,
+    /// The number of bytes sent.
+    pub sent: Bytes,
+    /// The number of bytes that crossed the stream's link.
+    pub delivered: Bytes,
+    /// The number of bytes dropped at the stream's link.
+    pub dropped: Bytes,
+}
+
+/// Source statistics.
+#[derive(Debug, Clone, Copy, serde::Serialize, serde::Deserialize)]
+pub struct SourceStats {
+    /// The source ID.
+    pub id: SourceId,
+    /// The number of PFC pauses received.
+    pub nr_pauses: usize,
+    /// The total time queue classes were paused, summed over classes.
+    pub paused_for: Nanosecs,
+}
+
 /// An flow completion time record.
 #[derive(Debug, Clone, Copy, serde::Serialize, serde::Deserialize)]
 pub struct Record {
@@ -22,6 +90,50 @@ pub struct Record {
     pub fct: Nanosecs,
     /// The ideal flow completion time in an unloaded simulation.
     pub ideal: Nanosecs,
+    /// The number of packets retransmitted before the flow completed.
+    #[serde(default)]
+    pub retransmits: usize,
+    /// The number of packets dropped at the bottleneck.
+    #[serde(default)]
+    pub drops: usize,
+    /// The time the flow spent at each MLFQ level, where level `i` is the queue `i` places below
+    /// `qindex`. A flow enters its initial queue when it starts and any other queue when it first
+    /// sends into it. Without an MLFQ classifier, the flow spends its FCT at level zero.
+    #[serde(default)]
+    pub queue_times: [Nanosecs; MAX_MLFQ_LEVELS],
+    /// The coflow the flow belongs to, if any.
+    #[serde(default)]
+    pub coflow: Option<CoflowId>,
+    /// The flow's deadline, relative to its start, if any.
+    #[serde(default)]
+    pub deadline: Option<Nanosecs>,
+}
+
+/// A coflow completion time record.
+#[derive(Debug, Clone, Copy, serde::Serialize, serde::Deserialize)]
+pub struct CoflowRecord {
+    /// The coflow ID.
+    pub id: CoflowId,
+    /// The number of flows in the coflow.
+    pub nr_flows: usize,
+    /// The total size of the coflow's flows.
+    pub size: Bytes,
+    /// The start time of the coflow's first flow.
+    pub start: Nanosecs,
+    /// The coflow completion time, from the start of its first flow to the completion of its
+    /// last flow.
+    pub cct: Nanosecs,
+    /// The ideal coflow completion time, set by the flow that would complete last if every flow
+    /// completed in its ideal FCT.
+    pub ideal: Nanosecs,
+}
+
+impl CoflowRecord {
+    /// Computes the delay experienced by the coflow, defined as the measured CCT minus the ideal
+    /// CCT.
+    pub fn delay(&self) -> Nanosecs {
+        self.cct.saturating_sub(self.ideal)
+    }
 }
 
 impl Record {
@@ -34,4 +146,16 @@ impl Record {
             Ordering::Greater => self.fct - self.ideal,
         }
     }
+
+    /// Returns whether the corresponding flow completed by its deadline, if it has one.
+    pub fn met_deadline(&self) -> Option<bool> {
+        self.deadline.map(|deadline| self.fct <= deadline)
+    }
+
+    /// Returns the amount of time by which the corresponding flow missed its deadline, if it has
+    /// one. The result is zero if the deadline was met.
+    pub fn deadline_miss(&self) -> Option<Nanosecs> {
+        self.deadline
+            .map(|deadline| self.fct.saturating_sub(deadline))
+    }
 }
@@ -1,39 +1,136 @@
-use std::path::Path;
+use std::{path::Path, rc::Rc};
 
-use rustc_hash::FxHashMap;
+use rustc_hash::{FxHashMap, FxHashSet};
 
 use crate::{
-    entities::{bottleneck::Bottleneck, source::Source, workload::Workload},
-    port::Port,
+    aqm::AqmKind,
+    cc::{CcKind, CcParams, DcqcnConfig, HpccConfig, SwiftConfig, TimelyConfig},
+    entities::{
+        background::{Background, BackgroundDesc, TrafficPattern},
+        bottleneck::{AckPath, BandwidthTrace, Bottleneck, LinkDesc, LinkId, PfcConfig, RedConfig},
+        destination::DestDesc,
+        source::Source,
+        workload::Workload,
+    },
+    flow::{Mlfq, MAX_MLFQ_LEVELS},
+    port::{Port, SchedulerKind, SharedBuffer},
+    receiver::DelayedAck,
     simulation::Simulation,
     units::{BitsPerSec, Bytes, Nanosecs},
-    FlowDesc, Record, SourceDesc,
+    FlowDesc, LossRecovery, Output, Record, SourceDesc,
 };
 
-/// A simulation configuration.
+/// A simulation configuration, built with [`Config::builder`].
 #[derive(Debug, typed_builder::TypedBuilder)]
+#[non_exhaustive]
 pub struct Config {
     /// The bottleneck bandwidth.
     #[builder(setter(into))]
     pub bandwidth: BitsPerSec,
+    /// The bottleneck capacity schedule, if the bandwidth varies over time. Ideal FCTs are still
+    /// computed with the configured bandwidth.
+    #[builder(default)]
+    pub bandwidth_trace: BandwidthTrace,
+    /// Additional bottleneck links, forming a multi-hop topology together with the top-level
+    /// bottleneck, which has the ID zero. Flows choose their links with [FlowDesc::path].
+    #[builder(default)]
+    pub links: Vec<LinkDesc>,
+    /// The reverse link crossed by ACKs, if any. Otherwise, ACKs reach their sources after a fixed
+    /// propagation delay.
+    #[builder(default, setter(strip_option))]
+    pub ack_path: Option<AckPath>,
     /// The list of sources.
     pub sources: Vec<SourceDesc>,
+    /// The list of destinations, each receiving through its own downlink. Flows choose their
+    /// destinations with [FlowDesc::dst].
+    #[builder(default)]
+    pub destinations: Vec<DestDesc>,
     /// The list of flows.
     pub flows: Vec<FlowDesc>,
+    /// The background traffic streams, which compete with the flows without reacting to
+    /// congestion.
+    #[builder(default)]
+    pub background: Vec<BackgroundDesc>,
     /// The switch weights.
     pub quanta: Vec<Bytes>,
+    /// The switch scheduling policy.
+    #[builder(default)]
+    pub scheduler: SchedulerKind,
+    /// The byte thresholds of a PIAS-style multi-level feedback queue classifier, if nonempty.
+    /// A flow starts in its queue and moves down one queue each time the number of bytes it has
+    /// sent, including retransmissions, crosses the next threshold, stopping at the last queue.
+    /// At most seven thresholds are allowed.
+    #[builder(default)]
+    pub mlfq_thresholds: Vec<Bytes>,
+    /// Whether each switch queue serves packets in priority order instead of FIFO order, as in
+    /// pFabric. On overflow, the lowest-priority packets are dropped first.
+    #[builder(default)]
+    pub priority_queues: bool,
+    /// The buffer size of each switch queue, if finite.
+    #[builder(default, setter(into, strip_option))]
+    pub queue_buffer: Option<Bytes>,
+    /// The buffer size shared by all switch queues, if finite.
+    #[builder(default, setter(into, strip_option))]
+    pub port_buffer: Option<Bytes>,
+    /// The shared switch buffer, if any. Admission into each queue is then limited by a dynamic
+    /// threshold.
+    #[builder(default, setter(strip_option))]
+    pub shared_buffer: Option<SharedBuffer>,
+    /// Priority-based flow control, if enabled.
+    #[builder(default, setter(strip_option))]
+    pub pfc: Option<PfcConfig>,
+    /// ACK coalescing at the receivers, if enabled. Otherwise, every packet is acknowledged.
+    #[builder(default, setter(strip_option))]
+    pub delayed_ack: Option<DelayedAck>,
+    /// The loss recovery parameters, used if any buffer is finite.
+    #[builder(default)]
+    pub loss_recovery: LossRecovery,
 
     /// The sending window.
     #[builder(setter(into))]
     pub window: Bytes,
+    /// The initial sending rate of every flow, if below the rate of the flow's source link.
+    #[builder(default, setter(into, strip_option))]
+    pub initial_rate: Option<BitsPerSec>,
+    /// The initial window of every flow, if flows start in slow start. In slow start, a flow's
+    /// window grows by the number of bytes acknowledged until its first ECN mark or loss, or
+    /// until it reaches the congestion control window.
+    #[builder(default, setter(into, strip_option))]
+    pub initial_window: Option<Bytes>,
     /// The DCTCP marking threshold.
     #[builder(setter(into))]
     pub dctcp_marking_threshold: Bytes,
+    /// Probabilistic RED-style marking, used instead of the marking threshold if set.
+    #[builder(default, setter(strip_option))]
+    pub red: Option<RedConfig>,
+    /// The active queue management policy of each switch queue. If empty, every queue uses
+    /// [ECN marking](AqmKind::Ecn).
+    #[builder(default)]
+    pub aqm: Vec<AqmKind>,
     /// The DCTCP gain.
     pub dctcp_gain: f64,
     /// The DCTCP additive increase.
     #[builder(setter(into))]
     pub dctcp_ai: BitsPerSec,
+    /// Whether window-based DCTCP paces packets over the RTT instead of sending them as fast as
+    /// ACKs allow.
+    #[builder(default)]
+    pub dctcp_pacing: bool,
+    /// The default congestion control algorithm.
+    #[builder(default)]
+    pub cc: CcKind,
+    /// The DCQCN parameters.
+    #[builder(default)]
+    pub dcqcn: DcqcnConfig,
+    /// The HPCC parameters.
+    #[builder(default)]
+    pub hpcc: HpccConfig,
+    /// The TIMELY parameters.
+    #[builder(default)]
+    pub timely: TimelyConfig,
+    /// The Swift parameters.
+    #[builder(default)]
+    pub swift: SwiftConfig,
 
     /// The maximum packet size.
     #[builder(setter(into))]
@@ -45,12 +142,20 @@ pub struct Config {
     /// The simulation timeout, if any.
     #[builder(default, setter(into, strip_option))]
     pub timeout: Option<Nanosecs>,
+    /// The seed for all randomized decisions made during the simulation.
+    #[builder(default)]
+    pub seed: u64,
 }
 
 /// Runs the simulation specified by `cfg` and returns a list of [records](Record).
-pub fn run(mut cfg: Config) -> Result<Vec<Record>, Error> {
+pub fn run(cfg: Config) -> Result<Vec<Record>, Error> {
+    run_with_stats(cfg).map(|output| output.records)
+}
+
+/// Runs the simulation specified by `cfg` and returns the [records](Record) along with
+/// bottleneck statistics.
+pub fn run_with_stats(mut cfg: Config) -> Result<Output, Error> {
     cfg.flows.sort_by_key(|f| f.start);
-    let workload = Workload::new(cfg.flows.into());
     let sources = cfg
         .sources
         .into_iter()
@@ -59,28 +164,297 @@ pub fn run(mut cfg: Config) -> Result<Vec<Record>, Error> {
                 .id(s.id)
                 .delay2btl(s.delay2btl)
                 .link_rate(s.link_rate)
+                .cc(s.cc)
+                .schedule(s.schedule)
                 .build();
             (s.id, source)
         })
         .collect::<FxHashMap<_, _>>();
+    if cfg.flows.iter().any(|f| f.weight == Some(0)) {
+        return Err(Error::InvalidWeights);
+    }
+    // Dependencies must refer to known flows and must not form cycles
+    let mut nr_deps = cfg
+        .flows
+        .iter()
+        .map(|f| (f.id, f.depends_on.len()))
+        .collect::<FxHashMap<_, _>>();
+    let mut dependents = FxHashMap::<_, Vec<_>>::default();
+    for flow in &cfg.flows {
+        for dep in &flow.depends_on {
+            if !nr_deps.contains_key(dep) {
+                return Err(Error::InvalidDependencies);
+            }
+            dependents.entry(*dep).or_default().push(flow.id);
+        }
+    }
+    let mut ready = nr_deps
+        .iter()
+        .filter(|&(_, &n)| n == 0)
+        .map(|(&id, _)| id)
+        .collect::<Vec<_>>();
+    let mut nr_resolved = 0;
+    while let Some(id) = ready.pop() {
+        nr_resolved += 1;
+        for dependent in dependents.remove(&id).unwrap_or_default() {
+            let n = nr_deps.get_mut(&dependent).unwrap();
+            *n -= 1;
+            if *n == 0 {
+                ready.push(dependent);
+            }
+        }
+    }
+    if nr_resolved != nr_deps.len() {
+        return Err(Error::InvalidDependencies);
+    }
+    let is_zero = |rate: Option<BitsPerSec>, window: Option<Bytes>| {
+        rate == Some(BitsPerSec::ZERO) || window == Some(Bytes::ZERO)
+    };
+    if is_zero(cfg.initial_rate, cfg.initial_window)
+        || cfg
+            .flows
+            .iter()
+            .any(|f| is_zero(f.initial_rate, f.initial_window))
+    {
+        return Err(Error::InvalidInitialState);
+    }
     if !cfg.quanta.iter().all(|&q| q > Bytes::ZERO) {
         return Err(Error::InvalidQuanta);
     }
-    let bottleneck = Bottleneck::builder()
-        .bandwidth(cfg.bandwidth)
-        .port(Port::new(&cfg.quanta))
-        .marking_threshold(cfg.dctcp_marking_threshold)
-        .build();
+    if cfg.red.is_some_and(|red| !(0.0..=1.0).contains(&red.pmax)) {
+        return Err(Error::RedPmaxOutOfRange);
+    }
+    if let SchedulerKind::SpDrr { levels } = &cfg.scheduler {
+        if levels.len() != cfg.quanta.len() {
+            return Err(Error::InvalidLevels);
+        }
+    }
+    if let Some(shared_buffer) = &cfg.shared_buffer {
+        if shared_buffer.alphas.len() != cfg.quanta.len()
+            || !shared_buffer.alphas.iter().all(|&a| a > 0.0)
+        {
+            return Err(Error::InvalidAlphas);
+        }
+    }
+    // Every link has a port with the same configuration
+    let new_port = || {
+        let mut port = Port::new(&cfg.quanta, &cfg.scheduler);
+        if let Some(shared_buffer) = &cfg.shared_buffer {
+            port = port.with_shared_buffer(shared_buffer.clone());
+        }
+        if cfg.priority_queues {
+            port = port.with_priority_queues();
+        }
+        port
+    };
+    if cfg.mlfq_thresholds.len() >= MAX_MLFQ_LEVELS
+        || !cfg.mlfq_thresholds.windows(2).all(|w| w[0] < w[1])
+        || cfg
+            .mlfq_thresholds
+            .first()
+            .is_some_and(|&t| t == Bytes::ZERO)
+    {
+        return Err(Error::InvalidThresholds);
+    }
+    let mlfq = (!cfg.mlfq_thresholds.is_empty()).then(|| {
+        Rc::new(Mlfq {
+            thresholds: cfg.mlfq_thresholds,
+            nr_queues: cfg.quanta.len(),
+        })
+    });
+    if cfg.pfc.is_some_and(|pfc| pfc.xon > pfc.xoff) {
+        return Err(Error::PfcXonAboveXoff);
+    }
+    let aqm = if cfg.aqm.is_empty() {
+        vec![AqmKind::default(); cfg.quanta.len()]
+    } else if cfg.aqm.len() == cfg.quanta.len() {
+        cfg.aqm
+    } else {
+        return Err(Error::InvalidAqm);
+    };
+    if !aqm.iter().all(|kind| kind.is_valid()) {
+        return Err(Error::InvalidAqmParams);
+    }
+    // The top-level bottleneck is the first link
+    let mut links = vec![LinkDesc {
+        id: LinkId::ZERO,
+        bandwidth: cfg.bandwidth,
+        delay: Nanosecs::ZERO,
+        dctcp_marking_threshold: cfg.dctcp_marking_threshold,
+        bandwidth_trace: cfg.bandwidth_trace,
+    }];
+    links.extend(cfg.links);
+    if !links.iter().all(|link| {
+        link.bandwidth_trace
+            .steps
+            .windows(2)
+            .all(|w| w[0].0 < w[1].0)
+    }) {
+        return Err(Error::InvalidTrace);
+    }
+    // A link that never comes back up would hold on to its packets forever
+    if links.iter().any(|link| {
+        link.bandwidth_trace
+            .steps
+            .last()
+            .is_some_and(|&(_, bw)| bw == BitsPerSec::ZERO)
+    }) {
+        return Err(Error::InvalidOutage);
+    }
+    let delays = links
+        .iter()
+        .map(|link| (link.id, link.delay))
+        .collect::<FxHashMap<_, _>>();
+    if delays.len() != links.len() {
+        return Err(Error::InvalidLinks);
+    }
+    let downlinks = cfg
+        .destinations
+        .iter()
+        .map(|dst| (dst.id, dst.downlink))
+        .collect::<FxHashMap<_, _>>();
+    if downlinks.len() != cfg.destinations.len()
+        || !downlinks
+            .values()
+            .all(|&link| link != LinkId::ZERO && delays.contains_key(&link))
+    {
+        return Err(Error::InvalidDestinations);
+    }
+    // A flow to a destination ends at the destination's downlink
+    for flow in &mut cfg.flows {
+        if let Some(dst) = flow.dst {
+            let Some(&downlink) = downlinks.get(&dst) else {
+                return Err(Error::InvalidDestinations);
+            };
+            flow.path = [flow.links(), &[downlink]].concat();
+        }
+    }
+    if cfg.delayed_ack.is_some_and(|d| d.nr_packets == 0) {
+        return Err(Error::InvalidDelayedAck);
+    }
+    let ack_path = match cfg.ack_path {
+        Some(path) if path.link == LinkId::ZERO || path.qindex.inner() >= cfg.quanta.len() => {
+            return Err(Error::InvalidAckPath);
+        }
+        Some(path) => {
+            let Some(&delay) = delays.get(&path.link) else {
+                return Err(Error::InvalidAckPath);
+            };
+            Some((path, delay))
+        }
+        None => None,
+    };
+    let is_valid_stream = |stream: &BackgroundDesc| {
+        let is_valid_pattern = match stream.pattern {
+            TrafficPattern::Cbr => true,
+            TrafficPattern::OnOff { mean_on, mean_off } => {
+                mean_on > Nanosecs::ZERO && mean_off > Nanosecs::ZERO
+            }
+        };
+        delays.contains_key(&stream.link)
+            && stream.qindex.inner() < cfg.quanta.len()
+            && stream.rate > BitsPerSec::ZERO
+            && is_valid_pattern
+    };
+    let background = cfg
+        .background
+        .iter()
+        .map(|&stream| {
+            let seed = cfg.seed.wrapping_add(stream.id.into_usize() as u64);
+            (stream.id, Background::new(stream, seed))
+        })
+        .collect::<FxHashMap<_, _>>();
+    if background.len() != cfg.background.len() || !cfg.background.iter().all(is_valid_stream) {
+        return Err(Error::InvalidBackground);
+    }
+    // Each link forwards packets to the next link on their flow's path
+    let mut routes = FxHashMap::<LinkId, FxHashMap<_, _>>::default();
+    for flow in &cfg.flows {
+        let path = flow.links();
+        let mut visited = FxHashSet::default();
+        let mut delay = sources
+            .get(&flow.source)
+            .map_or(Nanosecs::ZERO, |s| s.delay2btl);
+        for (i, id) in path.iter().enumerate() {
+            let Some(&link_delay) = delays.get(id) else {
+                return Err(Error::InvalidPath);
+            };
+            if !visited.insert(id) {
+                return Err(Error::InvalidPath);
+            }
+            if i > 0 {
+                delay += link_delay;
+                routes
+                    .entry(path[i - 1])
+                    .or_default()
+                    .insert(flow.id, (*id, link_delay));
+            }
+        }
+        if delay > flow.delay2dst {
+            return Err(Error::InvalidPath);
+        }
+    }
+    let bottlenecks = links
+        .into_iter()
+        .map(|link| {
+            let bottleneck = Bottleneck::builder()
+                .id(link.id)
+                .bandwidth(link.bandwidth)
+                .trace(link.bandwidth_trace)
+                .port(new_port())
+                .aqms(
+                    aqm.iter()
+                        .map(|kind| kind.build(link.dctcp_marking_threshold, cfg.red))
+                        .collect(),
+                )
+                .rng(cfg.seed.wrapping_add(link.id.into_usize() as u64))
+                .queue_buffer(cfg.queue_buffer)
+                .port_buffer(cfg.port_buffer)
+                .pfc(cfg.pfc)
+                .routes(routes.remove(&link.id).unwrap_or_default())
+                .ack_path(ack_path.filter(|(path, _)| path.link != link.id))
+                .delayed_ack(cfg.delayed_ack)
+                .build();
+            (link.id, bottleneck)
+        })
+        .collect();
+    let nr_flows = cfg.flows.iter().filter(|f| f.size > Bytes::ZERO).count();
+    let mut coflows = FxHashMap::default();
+    for flow in cfg.flows.iter().filter(|f| f.size > Bytes::ZERO) {
+        if let Some(id) = flow.coflow {
+            *coflows.entry(id).or_default() += 1;
+        }
+    }
+    let workload = Workload::new(cfg.flows);
+    let is_lossy = cfg.queue_buffer.is_some()
+        || cfg.port_buffer.is_some()
+        || cfg.shared_buffer.is_some()
+        || aqm.iter().any(|kind| kind.can_drop());
     let sim = Simulation::builder()
         .workload(workload)
         .sources(sources)
-        .bottleneck(bottleneck)
-        .window(cfg.window)
-        .dctcp_gain(cfg.dctcp_gain)
-        .dctcp_ai(cfg.dctcp_ai)
+        .bottlenecks(bottlenecks)
+        .background(background)
+        .cc_params(CcParams {
+            default: cfg.cc,
+            window: cfg.window,
+            initial_rate: cfg.initial_rate,
+            initial_window: cfg.initial_window,
+            dctcp_gain: cfg.dctcp_gain,
+            dctcp_ai: cfg.dctcp_ai,
+            dctcp_pacing: cfg.dctcp_pacing,
+            dcqcn: cfg.dcqcn,
+            hpcc: cfg.hpcc,
+            timely: cfg.timely,
+            swift: cfg.swift,
+        })
         .sz_pktmax(cfg.sz_pktmax)
         .sz_pkthdr(cfg.sz_pkthdr)
+        .loss_recovery(is_lossy.then_some(cfg.loss_recovery))
+        .mlfq(mlfq)
         .timeout(cfg.timeout.map(|v| v.into_time()))
+        .nr_flows_left(nr_flows)
+        .coflows(coflows)
         .build();
     Ok(sim.run())
 }
@@ -91,6 +465,82 @@ pub enum Error {
     /// Switch quanta must be positive.
     #[error("Switch quanta must be positive")]
     InvalidQuanta,
+
+    /// The RED marking probability must be between zero and one.
+    #[error("The RED marking probability must be between zero and one")]
+    RedPmaxOutOfRange,
+
+    /// Flow weights must be positive.
+    #[error("Flow weights must be positive")]
+    InvalidWeights,
+
+    /// Flow dependencies must refer to known flows and must not form cycles.
+    #[error("Flow dependencies must refer to known flows and must not form cycles")]
+    InvalidDependencies,
+
+    /// Initial rates and windows must be positive.
+    #[error("Initial rates and windows must be positive")]
+    InvalidInitialState,
+
+    /// There must be one positive alpha per switch queue.
+    #[error("There must be one positive alpha per switch queue")]
+    InvalidAlphas,
+
+    /// There must be one priority level per switch queue.
+    #[error("There must be one priority level per switch queue")]
+    InvalidLevels,
+
+    /// There must be one AQM policy per switch queue.
+    #[error("There must be one AQM policy per switch queue")]
+    InvalidAqm,
+
+    /// CoDel targets and intervals and PIE update intervals must be positive.
+    #[error("CoDel targets and intervals and PIE update intervals must be positive")]
+    InvalidAqmParams,
+
+    /// Additional links must have distinct nonzero IDs.
+    #[error("Additional links must have distinct nonzero IDs")]
+    InvalidLinks,
+
+    /// Flow paths must consist of distinct known links, and the delays along a path must not
+    /// exceed the flow's propagation delay.
+    #[error("Flow paths must consist of distinct known links within the flow's propagation delay")]
+    InvalidPath,
+
+    /// Destinations must have distinct IDs and downlinks that are additional links, and flows
+    /// must refer to known destinations.
+    #[error("Destinations must have distinct IDs and additional links as downlinks")]
+    InvalidDestinations,
+
+    /// The ACK path must refer to an additional link and a valid queue.
+    #[error("The ACK path must refer to an additional link and a valid queue")]
+    InvalidAckPath,
+
+    /// Background streams must have distinct IDs, known links, valid queues, positive rates,
+    /// and positive on and off periods.
+    #[error("Background streams must have distinct IDs, known links, valid queues, and positive rates and periods")]
+    InvalidBackground,
+
+    /// Delayed ACKs must cover at least one packet.
+    #[error("Delayed ACKs must cover at least one packet")]
+    InvalidDelayedAck,
+
+    /// Bandwidth trace steps must be in strictly increasing order of time.
+    #[error("Bandwidth trace steps must be in strictly increasing order of time")]
+    InvalidTrace,
+
+    /// Bandwidth traces must not end with an outage.
+    #[error("Bandwidth traces must not end with an outage")]
+    InvalidOutage,
+
+    /// There must be at most seven MLFQ thresholds, and they must be positive and strictly
+    /// increasing.
+    #[error("There must be at most seven MLFQ thresholds, positive and strictly increasing")]
+    InvalidThresholds,
+
+    /// The PFC XON threshold must not exceed the XOFF threshold.
+    #[error("The PFC XON threshold must not exceed the XOFF threshold")]
+    PfcXonAboveXoff,
 }
 
 /// Reads a list of [flows](FlowDesc) from `path`.
@@ -99,6 +549,13 @@ pub fn read_flows(path: impl AsRef<Path>) -> Result<Vec<FlowDesc>, ReadFlowsErro
     Ok(serde_json::from_str(&s)?)
 }
 
+/// Reads a [bandwidth trace](BandwidthTrace) from `path`. The trace is a JSON list of
+/// `[time, bandwidth]` pairs, in nanoseconds and bits per second.
+pub fn read_bandwidth_trace(path: impl AsRef<Path>) -> Result<BandwidthTrace, ReadTraceError> {
+    let s = std::fs::read_to_string(path)?;
+    Ok(serde_json::from_str(&s)?)
+}
+
 /// The error type returned by [read_flows].
 #[derive(Debug, thiserror::Error)]
 pub enum ReadFlowsError {
@@ -110,3 +567,15 @@ pub enum ReadFlowsError {
     #[error("IO error")]
     Io(#[from] std::io::Error),
 }
+
+/// The error type returned by [read_bandwidth_trace].
+#[derive(Debug, thiserror::Error)]
+pub enum ReadTraceError {
+    /// Serialization/deserialization error.
+    #[error("serde error")]
+    Serde(#[from] serde_json::Error),
+
+    /// IO error.
+    #[error("IO error")]
+    Io(#[from] std::io::Error),
+}
@@ -1,3 +1,5 @@
+pub(crate) mod background;
 pub(crate) mod bottleneck;
+pub(crate) mod destination;
 pub(crate) mod source;
 pub(crate) mod workload;
@@ -0,0 +1,193 @@
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+use crate::{
+    data::BackgroundStats,
+    entities::bottleneck::{BottleneckCmd, LinkId},
+    packet::Packet,
+    port::QIndex,
+    simulation::{event::EventList, Context},
+    time::{Delta, Time},
+    units::{BitsPerSec, Bytes, Nanosecs},
+    FlowId, SourceId,
+};
+
+identifier!(BackgroundId);
+
+#[derive(Debug)]
+pub(crate) struct Background {
+    desc: BackgroundDesc,
+    rng: StdRng,
+    // The end of the current on period
+    on_until: Time,
+    sent: Bytes,
+}
+
+impl Background {
+    pub(crate) fn new(desc: BackgroundDesc, seed: u64) -> Self {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let on_until = match desc.pattern {
+            TrafficPattern::Cbr => Time::MAX,
+            TrafficPattern::OnOff { mean_on, .. } => {
+                desc.start.into_time() + sample_exp(&mut rng, mean_on)
+            }
+        };
+        Self {
+            desc,
+            rng,
+            on_until,
+            sent: Bytes::ZERO,
+        }
+    }
+
+    pub(crate) fn id(&self) -> BackgroundId {
+        self.desc.id
+    }
+
+    pub(crate) fn start(&self) -> Time {
+        self.desc.start.into_time()
+    }
+
+    // Whether the stream keeps sending until the flows are done, rather than until a fixed time
+    pub(crate) fn is_open_ended(&self) -> bool {
+        self.desc.stop.is_none()
+    }
+
+    #[must_use]
+    pub(crate) fn send(&mut self, mut ctx: Context) -> EventList {
+        if self
+            .desc
+            .stop
+            .is_some_and(|stop| ctx.cur_time >= stop.into_time())
+        {
+            return ctx.into_events();
+        }
+        if let TrafficPattern::OnOff { mean_on, mean_off } = self.desc.pattern {
+            if ctx.cur_time >= self.on_until {
+                // Stay silent for an off period, then start a new on period
+                let off = sample_exp(&mut self.rng, mean_off);
+                let on = sample_exp(&mut self.rng, mean_on);
+                self.on_until = ctx.cur_time + off + on;
+                ctx.schedule(off, BackgroundCmd::new_send(self.desc.id));
+                return ctx.into_events();
+            }
+        }
+        let size = ctx.sz_pktmax + ctx.sz_pkthdr;
+        let pkt = Packet::builder()
+            .flow_id(FlowId::ZERO)
+            .source_id(SourceId::ZERO)
+            .qindex(self.desc.qindex)
+            .link(self.desc.link)
+            .seq(self.sent)
+            .size(size)
+            .src2btl(Nanosecs::ZERO)
+            .btl2dst(Nanosecs::ZERO)
+            .is_last(false)
+            .is_retx(false)
+            .priority(u64::MAX)
+            .sent_at(ctx.cur_time)
+            .background(Some(self.desc.id))
+            .build();
+        self.sent += size;
+        ctx.schedule(Delta::ZERO, BottleneckCmd::new_receive(pkt));
+        ctx.schedule(
+            self.desc.rate.length(size).into_delta(),
+            BackgroundCmd::new_send(self.desc.id),
+        );
+        ctx.into_events()
+    }
+
+    pub(crate) fn stats(&self) -> BackgroundStats {
+        BackgroundStats {
+            id: self.desc.id,
+            sent: self.sent,
+            delivered: Bytes::ZERO,
+            dropped: Bytes::ZERO,
+        }
+    }
+}
+
+// Samples an exponentially distributed duration with the given mean.
+fn sample_exp(rng: &mut StdRng, mean: Nanosecs) -> Delta {
+    let u: f64 = rng.gen();
+    let t = -(1.0 - u).ln() * mean.into_f64();
+    Delta::new(t.round() as u128)
+}
+
+#[derive(Debug, Clone, Copy, derive_new::new)]
+pub(crate) enum BackgroundCmd {
+    Send(BackgroundId),
+}
+
+/// A background traffic stream.
+///
+/// Background traffic does not react to congestion: it is sent at a fixed rate regardless of
+/// marks, drops, and PFC pauses, and it is never acknowledged. Its packets enter a single link
+/// and leave the network after crossing it. Built with [`BackgroundDesc::builder`].
+#[derive(Debug, Clone, Copy, typed_builder::TypedBuilder, serde::Serialize, serde::Deserialize)]
+#[non_exhaustive]
+pub struct BackgroundDesc {
+    /// The stream ID.
+    pub id: BackgroundId,
+    /// The link the stream crosses.
+    #[builder(default)]
+    #[serde(default)]
+    pub link: LinkId,
+    /// The queue index.
+    #[builder(default)]
+    #[serde(default)]
+    pub qindex: QIndex,
+    /// The sending rate while the stream is on.
+    #[builder(setter(into))]
+    pub rate: BitsPerSec,
+    /// The time the stream starts.
+    #[builder(default, setter(into))]
+    #[serde(default)]
+    pub start: Nanosecs,
+    /// The time the stream stops, if any. Otherwise, the stream stops once every flow has
+    /// completed.
+    #[builder(default, setter(into, strip_option))]
+    #[serde(default)]
+    pub stop: Option<Nanosecs>,
+    /// The traffic pattern.
+    #[builder(default)]
+    #[serde(default)]
+    pub pattern: TrafficPattern,
+}
+
+/// A background traffic pattern.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum TrafficPattern {
+    /// Constant bit rate.
+    #[default]
+    Cbr,
+    /// A Markov on/off source, which sends at the stream's rate during on periods. On and off
+    /// periods are exponentially distributed.
+    OnOff {
+        /// The mean duration of an on period.
+        mean_on: Nanosecs,
+        /// The mean duration of an off period.
+        mean_off: Nanosecs,
+    },
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn periods_are_exponential_with_the_given_mean() {
+        let mut rng = StdRng::seed_from_u64(1);
+        let mean = Nanosecs::new(100_000);
+        let samples = (0..10_000)
+            .map(|_| sample_exp(&mut rng, mean).into_f64())
+            .collect::<Vec<_>>();
+        let avg = samples.iter().sum::<f64>() / samples.len() as f64;
+        assert!((avg / mean.into_f64() - 1.0).abs() < 0.05);
+        // Half of an exponential distribution's mass lies below ln(2) times its mean
+        let below_median = samples
+            .iter()
+            .filter(|&&t| t < mean.into_f64() * 2f64.ln())
+            .count();
+        assert!((below_median as f64 / samples.len() as f64 - 0.5).abs() < 0.02);
+    }
+}
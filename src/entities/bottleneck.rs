@@ -1,28 +1,140 @@
+use rand::{rngs::StdRng, SeedableRng};
+use rustc_hash::{FxHashMap, FxHashSet};
+
 use crate::{
-    entities::source::SourceCmd,
-    packet::{Ack, Packet},
-    port::Port,
+    aqm::{Aqm, Verdict},
+    data::{LinkStats, QueueStats},
+    entities::{background::BackgroundId, source::SourceCmd},
+    packet::{Ack, IntHop, Packet},
+    port::{Port, QIndex},
+    receiver::{DelayedAck, Receiver},
     simulation::{event::EventList, Context},
-    units::{BitsPerSec, Bytes},
+    time::{Delta, Time},
+    units::{BitsPerSec, Bytes, Nanosecs},
+    FlowId, SourceId,
 };
 
+identifier!(LinkId);
+
 #[derive(Debug, typed_builder::TypedBuilder)]
 pub(crate) struct Bottleneck {
+    pub(crate) id: LinkId,
     #[builder(setter(into))]
     pub(crate) bandwidth: BitsPerSec,
+    #[builder(default)]
+    trace: BandwidthTrace,
     port: Port,
     #[builder(default, setter(skip))]
     status: Status,
+    #[builder(default, setter(skip))]
+    tx_bytes: Bytes,
 
-    #[builder(setter(into))]
-    marking_threshold: Bytes,
+    // Active queue management, one policy per queue
+    aqms: Vec<Box<dyn Aqm>>,
+    #[builder(setter(transform = |seed: u64| StdRng::seed_from_u64(seed)))]
+    rng: StdRng,
+
+    // Buffer limits
+    #[builder(default)]
+    queue_buffer: Option<Bytes>,
+    #[builder(default)]
+    port_buffer: Option<Bytes>,
+    #[builder(default, setter(skip))]
+    pub(crate) drops: FxHashMap<FlowId, usize>,
+
+    // The bytes of each background stream delivered and dropped at this link
+    #[builder(default, setter(skip))]
+    pub(crate) background: FxHashMap<BackgroundId, (Bytes, Bytes)>,
+
+    // Priority-based flow control
+    #[builder(default)]
+    pfc: Option<PfcConfig>,
+    #[builder(default, setter(skip))]
+    feeders: FxHashMap<QIndex, FxHashMap<SourceId, Feeder>>,
+    #[builder(default, setter(skip))]
+    paused: FxHashSet<QIndex>,
+
+    // The next hop of each flow that continues past this link, and the propagation delay to it.
+    // All other flows are delivered to their destinations.
+    #[builder(default)]
+    routes: FxHashMap<FlowId, (LinkId, Nanosecs)>,
+
+    // The reverse link that ACKs of flows ending here must cross, and the propagation delay from
+    // the destination to it
+    #[builder(default)]
+    ack_path: Option<(AckPath, Nanosecs)>,
+    // The contents of the ACKs queued at this link, if it is a reverse link
+    #[builder(default, setter(skip))]
+    acks: FxHashMap<u64, Ack>,
+    #[builder(default, setter(skip))]
+    next_ack: u64,
+
+    // ACK coalescing at the receiver, if enabled
+    #[builder(default)]
+    delayed_ack: Option<DelayedAck>,
+    #[builder(default, setter(skip))]
+    receiver: Receiver,
 }
 
 impl Bottleneck {
     #[must_use]
-    pub(crate) fn receive(&mut self, pkt: Packet, ctx: Context) -> EventList {
+    pub(crate) fn receive(&mut self, mut pkt: Packet, mut ctx: Context) -> EventList {
+        self.port.advance(ctx.cur_time);
+        pkt.enqueued_at = ctx.cur_time;
+        // Drop packets that don't fit in the buffer. Queues that serve packets by priority push
+        // out their lower-priority packets to make room; other queues tail-drop.
+        while !self.admits(&pkt) {
+            match self.port[pkt.qindex].lowest_priority() {
+                Some(lowest) if lowest > pkt.priority => {
+                    let victim = self.port[pkt.qindex]
+                        .drop_lowest()
+                        .expect("unexpected empty queue");
+                    self.record_drop(&victim);
+                    self.release_feeder(&victim);
+                }
+                _ => {
+                    self.record_drop(&pkt);
+                    return ctx.into_events();
+                }
+            }
+        }
+        let qsize = self.port[pkt.qindex].size();
+        match self.aqms[pkt.qindex.inner()].on_enqueue(&pkt, qsize, ctx.cur_time, &mut self.rng) {
+            Verdict::Pass => (),
+            Verdict::Mark => pkt.marked = true,
+            Verdict::Drop => {
+                self.record_drop(&pkt);
+                return ctx.into_events();
+            }
+        }
         // Enqueue the packet and update state
-        self.port[pkt.qindex].enqueue(pkt);
+        let (qindex, source, src2btl) = (pkt.qindex, pkt.source_id, pkt.src2btl);
+        // Only flows can be paused
+        let is_flow = pkt.ack.is_none() && pkt.background.is_none();
+        self.port[qindex].enqueue(pkt);
+        if let (Some(pfc), true) = (self.pfc, is_flow) {
+            let feeders = self.feeders.entry(qindex).or_default();
+            let feeder = feeders.entry(source).or_insert(Feeder {
+                delay: src2btl,
+                nr_queued: 0,
+                is_paused: false,
+            });
+            feeder.nr_queued += 1;
+            if self.paused.contains(&qindex) {
+                // Sources that start feeding a paused class are paused as well
+                if !feeder.is_paused {
+                    feeder.is_paused = true;
+                    ctx.schedule(src2btl.into_delta(), SourceCmd::new_pause(source, qindex));
+                }
+            } else if self.port[qindex].size() >= pfc.xoff {
+                self.paused.insert(qindex);
+                for (&source, feeder) in feeders.iter_mut() {
+                    feeder.is_paused = true;
+                    let delay = feeder.delay.into_delta();
+                    ctx.schedule(delay, SourceCmd::new_pause(source, qindex));
+                }
+            }
+        }
         match self.status {
             Status::Running => ctx.into_events(),
             Status::Blocked => {
@@ -32,32 +144,115 @@ impl Bottleneck {
         }
     }
 
+    #[must_use]
+    pub(crate) fn receive_ack(&mut self, mut pkt: Packet, ack: Ack, ctx: Context) -> EventList {
+        let id = self.next_ack;
+        self.next_ack += 1;
+        self.acks.insert(id, ack);
+        pkt.ack = Some(id);
+        self.receive(pkt, ctx)
+    }
+
     #[must_use]
     pub(crate) fn step(&mut self, mut ctx: Context) -> EventList {
         assert!(self.status == Status::Running);
-        match self.port.pick_dequeue_index() {
-            Some(qidx) => {
-                let pkt = self.port[qidx].dequeue().expect("unexpected empty queue");
+        self.port.advance(ctx.cur_time);
+        match self.dequeue(ctx.cur_time) {
+            Some((qidx, mut pkt)) => {
+                self.tx_bytes += pkt.size;
+                if let Some(pfc) = self.pfc {
+                    if self.port[qidx].size() <= pfc.xon && self.paused.remove(&qidx) {
+                        let feeders = self.feeders.entry(qidx).or_default();
+                        for (&source, feeder) in feeders.iter_mut() {
+                            feeder.is_paused = false;
+                            let delay = feeder.delay.into_delta();
+                            ctx.schedule(delay, SourceCmd::new_resume(source, qidx));
+                        }
+                        // Sources without queued packets no longer feed the class
+                        feeders.retain(|_, feeder| feeder.nr_queued > 0);
+                    }
+                }
                 // Service the packet
-                let bw_delta = self.bandwidth.length(pkt.size).into_delta();
-                ctx.schedule(bw_delta, BottleneckCmd::new_step());
+                let bw_delta = self
+                    .trace
+                    .transmit(self.bandwidth, ctx.cur_time, pkt.size)
+                    .expect("bandwidth traces end with a positive capacity");
+                ctx.schedule(bw_delta, BottleneckCmd::new_step(self.id));
+                if let Some(id) = pkt.background {
+                    // Background traffic leaves the network after crossing the link
+                    self.background.entry(id).or_default().0 += pkt.size;
+                    return ctx.into_events();
+                }
+                if let Some(id) = pkt.ack {
+                    // Deliver the ACK to the flow's source
+                    let ack = self.acks.remove(&id).expect("missing ACK");
+                    ctx.schedule(
+                        bw_delta + pkt.btl2dst.into_delta(),
+                        SourceCmd::new_rcv_ack(pkt.source_id, pkt.flow_id, ack),
+                    );
+                    return ctx.into_events();
+                }
+                // Stamp the link's telemetry for the flow's congestion control
+                pkt.int.push(IntHop {
+                    qlen: self.port[qidx].size(),
+                    tx_bytes: self.tx_bytes,
+                    ts: ctx.cur_time,
+                    bandwidth: self.trace.bandwidth_at(self.bandwidth, ctx.cur_time),
+                });
+                if let Some(&(next, delay)) = self.routes.get(&pkt.flow_id) {
+                    // Forward the packet to the next hop, carrying over any mark
+                    pkt.link = next;
+                    pkt.src2btl += delay;
+                    pkt.btl2dst -= delay;
+                    ctx.schedule(
+                        bw_delta + delay.into_delta(),
+                        BottleneckCmd::new_receive(pkt),
+                    );
+                    return ctx.into_events();
+                }
                 // Send an ACK back to the flow
-                let prop_delta = (pkt.btl2dst + pkt.hrtt()).into_delta();
-                let nr_bytes_to_ack = pkt.size - ctx.sz_pkthdr;
-                let marked = self.port[qidx].size() > self.marking_threshold;
-                ctx.schedule(
-                    bw_delta + prop_delta,
-                    SourceCmd::new_rcv_ack(
-                        pkt.source_id,
-                        pkt.flow_id,
-                        Ack::new(nr_bytes_to_ack, marked),
-                    ),
+                let payload = pkt.size - ctx.sz_pkthdr;
+                let delivery =
+                    self.receiver
+                        .receive(&pkt, payload, ctx.loss_recovery.map(|r| r.mode));
+                let ack = Ack::new(
+                    delivery.ackno,
+                    delivery.sack,
+                    delivery.nack,
+                    if pkt.marked { payload } else { Bytes::ZERO },
+                    pkt.int.clone(),
+                    pkt.sent_at,
                 );
-                if pkt.is_last {
+                // The time until the packet reaches the destination
+                let dst_delta = bw_delta + pkt.btl2dst.into_delta();
+                match self.delayed_ack {
+                    Some(cfg) => {
+                        let immediate = delivery.ackno != pkt.seq + payload
+                            || delivery.sack.is_some()
+                            || delivery.nack
+                            || pkt.is_retx
+                            || pkt.is_last;
+                        let arrived_at = ctx.cur_time + dst_delta;
+                        let coalesced = self
+                            .receiver
+                            .coalesce(&pkt, ack, arrived_at, immediate, cfg);
+                        for (pkt, ack) in coalesced.acks {
+                            self.send_ack(&pkt, ack, dst_delta, &mut ctx);
+                        }
+                        if let Some(version) = coalesced.timer {
+                            ctx.schedule(
+                                dst_delta + cfg.timeout.into_delta(),
+                                BottleneckCmd::new_ack_timer(self.id, pkt.flow_id, version),
+                            );
+                        }
+                    }
+                    None => self.send_ack(&pkt, ack, dst_delta, &mut ctx),
+                }
+                if delivery.is_complete {
                     // A flow is defined to be departed when all of its bytes
                     // have been delivered to the destination.
                     ctx.schedule(
-                        bw_delta + pkt.btl2dst.into_delta(),
+                        dst_delta,
                         SourceCmd::new_flow_depart(pkt.source_id, pkt.flow_id),
                     );
                 }
@@ -68,12 +263,266 @@ impl Bottleneck {
         }
         ctx.into_events()
     }
+
+    #[must_use]
+    pub(crate) fn ack_timeout(
+        &mut self,
+        flow: FlowId,
+        version: u64,
+        mut ctx: Context,
+    ) -> EventList {
+        if let Some((pkt, ack)) = self.receiver.ack_timeout(flow, version, ctx.cur_time) {
+            self.send_ack(&pkt, ack, Delta::ZERO, &mut ctx);
+        }
+        ctx.into_events()
+    }
+
+    // Sends the ACK for `pkt`, which leaves the destination after `dst_delta`.
+    fn send_ack(&self, pkt: &Packet, ack: Ack, dst_delta: Delta, ctx: &mut Context) {
+        match self.ack_path {
+            Some((path, delay)) => {
+                // The ACK queues at the reverse link on its way back
+                let ack_pkt = Packet::builder()
+                    .flow_id(pkt.flow_id)
+                    .source_id(pkt.source_id)
+                    .qindex(path.qindex)
+                    .link(path.link)
+                    .seq(ack.ackno)
+                    .size(path.ack_size)
+                    .src2btl(delay)
+                    .btl2dst(pkt.hrtt().saturating_sub(delay))
+                    .is_last(false)
+                    .is_retx(false)
+                    .priority(0)
+                    .sent_at(ctx.cur_time)
+                    .build();
+                ctx.schedule(
+                    dst_delta + delay.into_delta(),
+                    BottleneckCmd::new_receive_ack(ack_pkt, Box::new(ack)),
+                );
+            }
+            None => ctx.schedule(
+                dst_delta + pkt.hrtt().into_delta(),
+                SourceCmd::new_rcv_ack(pkt.source_id, pkt.flow_id, ack),
+            ),
+        }
+    }
+
+    fn admits(&self, pkt: &Packet) -> bool {
+        let fits_queue = self
+            .queue_buffer
+            .is_none_or(|limit| self.port[pkt.qindex].size() + pkt.size <= limit);
+        let fits_port = self
+            .port_buffer
+            .is_none_or(|limit| self.port.size() + pkt.size <= limit);
+        fits_queue && fits_port && self.port.admits(pkt.qindex, pkt.size)
+    }
+
+    // Dropped ACKs only count towards the queue's drops, and dropped background packets are
+    // accounted to their stream.
+    fn record_drop(&mut self, pkt: &Packet) {
+        match (pkt.ack, pkt.background) {
+            (Some(id), _) => {
+                self.acks.remove(&id);
+            }
+            (None, Some(id)) => self.background.entry(id).or_default().1 += pkt.size,
+            (None, None) => *self.drops.entry(pkt.flow_id).or_default() += 1,
+        }
+        self.port[pkt.qindex].drops += 1;
+    }
+
+    pub(crate) fn queue_stats(&self) -> Vec<QueueStats> {
+        self.port.stats()
+    }
+
+    pub(crate) fn stats(&self) -> LinkStats {
+        LinkStats {
+            id: self.id,
+            queues: self.queue_stats(),
+        }
+    }
+
+    // Accounts for a flow packet leaving its queue. A source stops feeding a queue class once it
+    // has no packets left in it, unless it is waiting to be resumed.
+    fn release_feeder(&mut self, pkt: &Packet) {
+        if pkt.ack.is_some() || pkt.background.is_some() {
+            return;
+        }
+        let Some(feeders) = self.feeders.get_mut(&pkt.qindex) else {
+            return;
+        };
+        let Some(feeder) = feeders.get_mut(&pkt.source_id) else {
+            return;
+        };
+        feeder.nr_queued -= 1;
+        if feeder.nr_queued == 0 && !feeder.is_paused {
+            feeders.remove(&pkt.source_id);
+        }
+    }
+
+    // Dequeues the next packet that the queue's AQM policy lets through, if any.
+    fn dequeue(&mut self, now: Time) -> Option<(QIndex, Packet)> {
+        loop {
+            let qidx = self.port.pick_dequeue_index()?;
+            let mut pkt = self.port[qidx].dequeue().expect("unexpected empty queue");
+            self.release_feeder(&pkt);
+            let qsize = self.port[qidx].size();
+            match self.aqms[qidx.inner()].on_dequeue(&pkt, qsize, now, &mut self.rng) {
+                Verdict::Pass => return Some((qidx, pkt)),
+                Verdict::Mark => {
+                    pkt.marked = true;
+                    return Some((qidx, pkt));
+                }
+                Verdict::Drop => self.record_drop(&pkt),
+            }
+        }
+    }
+}
+
+/// A schedule of bottleneck link capacities.
+///
+/// Each step `(t, bandwidth)` sets the link capacity from time `t` until the next step. Before the
+/// first step, the link runs at its configured bandwidth. A bandwidth of zero models an outage,
+/// which must end before the last step.
+#[derive(
+    Debug, Default, Clone, PartialEq, Eq, derive_new::new, serde::Serialize, serde::Deserialize,
+)]
+#[serde(transparent)]
+pub struct BandwidthTrace {
+    /// The capacity steps, in increasing order of time.
+    pub steps: Vec<(Nanosecs, BitsPerSec)>,
+}
+
+impl BandwidthTrace {
+    // Returns the capacity at `now`. `bandwidth` is the capacity before the first step.
+    pub(crate) fn bandwidth_at(&self, bandwidth: BitsPerSec, now: Time) -> BitsPerSec {
+        let t = now.into_ns();
+        let idx = self.steps.partition_point(|&(start, _)| start <= t);
+        idx.checked_sub(1).map_or(bandwidth, |i| self.steps[i].1)
+    }
+
+    // Returns the time needed to transmit `size` bytes starting at `now`, following the capacity
+    // changes along the way, or `None` if the link never has enough capacity again. `bandwidth`
+    // is the capacity before the first step.
+    pub(crate) fn transmit(&self, bandwidth: BitsPerSec, now: Time, size: Bytes) -> Option<Delta> {
+        let mut t = now.into_ns();
+        let idx = self.steps.partition_point(|&(start, _)| start <= t);
+        let mut bw = self.bandwidth_at(bandwidth, now);
+        let mut bits = size.into_bits().into_f64();
+        let mut elapsed = 0.0;
+        for &(next, next_bw) in &self.steps[idx..] {
+            let span = (next - t).into_f64();
+            let capacity = bw.into_f64() * span / 1e9;
+            if capacity >= bits {
+                break;
+            }
+            bits -= capacity;
+            elapsed += span;
+            (t, bw) = (next, next_bw);
+        }
+        if bw == BitsPerSec::ZERO {
+            return None;
+        }
+        elapsed += bits * 1e9 / bw.into_f64();
+        Some(Delta::new(elapsed.round() as u128))
+    }
+}
+
+/// Priority-based flow control (PFC) parameters.
+///
+/// When a queue grows to `xoff`, every source feeding that queue class is paused. The sources are
+/// resumed once the queue drains to `xon`. Built with [`PfcConfig::builder`].
+#[derive(Debug, Clone, Copy, typed_builder::TypedBuilder, serde::Serialize, serde::Deserialize)]
+#[non_exhaustive]
+pub struct PfcConfig {
+    /// The queue size at which the queue class is paused.
+    #[builder(setter(into))]
+    pub xoff: Bytes,
+    /// The queue size at which the queue class is resumed.
+    #[builder(setter(into))]
+    pub xon: Bytes,
+}
+
+/// RED-style probabilistic ECN marking parameters.
+///
+/// Packets are never marked below `kmin` and always marked above `kmax`. In between, the marking
+/// probability increases linearly from zero to `pmax`. Built with [`RedConfig::builder`].
+#[derive(Debug, Clone, Copy, typed_builder::TypedBuilder, serde::Serialize, serde::Deserialize)]
+#[non_exhaustive]
+pub struct RedConfig {
+    /// The queue size above which packets may be marked.
+    #[builder(setter(into))]
+    pub kmin: Bytes,
+    /// The queue size above which all packets are marked.
+    #[builder(setter(into))]
+    pub kmax: Bytes,
+    /// The marking probability at `kmax`.
+    pub pmax: f64,
+}
+
+/// A bottleneck link in addition to the one described by the top-level [configuration](crate::Config).
+/// Every link has its own switch port, built from the same queue configuration. Links are
+/// constructed with [`LinkDesc::builder`].
+#[derive(Debug, Clone, typed_builder::TypedBuilder, serde::Serialize, serde::Deserialize)]
+#[non_exhaustive]
+pub struct LinkDesc {
+    /// The link ID. The ID zero is reserved for the top-level bottleneck.
+    pub id: LinkId,
+    /// The link bandwidth.
+    #[builder(setter(into))]
+    pub bandwidth: BitsPerSec,
+    /// The propagation delay from the previous link on a flow's path to this link.
+    #[builder(setter(into))]
+    pub delay: Nanosecs,
+    /// The DCTCP marking threshold.
+    #[builder(setter(into))]
+    pub dctcp_marking_threshold: Bytes,
+    /// The capacity schedule, if the link bandwidth varies over time.
+    #[builder(default)]
+    #[serde(default)]
+    pub bandwidth_trace: BandwidthTrace,
+}
+
+/// The reverse link crossed by ACKs.
+///
+/// ACKs of flows that end at any other link queue at this link on their way back to the source,
+/// contending with the data of flows whose path ends at this link. The link's delay is the
+/// propagation delay from the destination to the link. Built with [`AckPath::builder`].
+#[derive(Debug, Clone, Copy, typed_builder::TypedBuilder, serde::Serialize, serde::Deserialize)]
+#[non_exhaustive]
+pub struct AckPath {
+    /// The reverse link ID, referring to one of the [additional links](crate::Config::links).
+    pub link: LinkId,
+    /// The size of an ACK.
+    #[builder(default = Bytes::new(64), setter(into))]
+    pub ack_size: Bytes,
+    /// The queue index of ACKs at the reverse link.
+    #[builder(default)]
+    #[serde(default)]
+    pub qindex: QIndex,
+}
+
+// A source feeding a queue class under PFC
+#[derive(Debug, Clone, Copy)]
+struct Feeder {
+    // The propagation delay from the source to the link
+    delay: Nanosecs,
+    // The number of the source's packets in the queue
+    nr_queued: usize,
+    // Whether the source has been paused and not yet resumed
+    is_paused: bool,
 }
 
 #[derive(Debug, Clone, derive_new::new)]
 pub(crate) enum BottleneckCmd {
     Receive(Packet),
-    Step,
+    ReceiveAck(Packet, Box<Ack>),
+    Step(LinkId),
+    AckTimer {
+        link: LinkId,
+        flow: FlowId,
+        version: u64,
+    },
 }
 
 #[derive(Debug, Copy, Clone, PartialEq, Eq, derive_new::new, derivative::Derivative)]
@@ -83,3 +532,37 @@ enum Status {
     #[derivative(Default)]
     Blocked,
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::units::Gbps;
+
+    use super::*;
+
+    #[test]
+    fn transmit_follows_trace() {
+        let bw = Gbps::new(10).into_bps();
+        let trace = BandwidthTrace::new(vec![
+            (Nanosecs::new(1_000), BitsPerSec::ZERO),
+            (Nanosecs::new(2_000), Gbps::new(1).into_bps()),
+        ]);
+        let size = Bytes::new(1_250);
+        // Before the first step, the configured bandwidth applies
+        assert_eq!(
+            trace.transmit(bw, Time::ZERO, size),
+            Some(Delta::new(1_000))
+        );
+        // Half of the packet is sent before the outage and the rest after it
+        assert_eq!(
+            trace.transmit(bw, Time::new(500), size),
+            Some(Delta::new(500 + 1_000 + 5_000))
+        );
+        assert_eq!(
+            trace.transmit(bw, Time::new(3_000), size),
+            Some(Delta::new(10_000))
+        );
+        // The link never comes back up
+        let outage = BandwidthTrace::new(vec![(Nanosecs::new(1_000), BitsPerSec::ZERO)]);
+        assert_eq!(outage.transmit(bw, Time::new(500), size), None);
+    }
+}
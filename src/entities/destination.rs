@@ -0,0 +1,18 @@
+use crate::entities::bottleneck::LinkId;
+
+identifier!(DestId);
+
+/// A destination configuration.
+///
+/// A destination receives traffic through its downlink, which is the last link on the path of
+/// every flow to the destination. Destinations with different downlinks share nothing, so an
+/// incast onto one of them leaves the others unaffected. Built with [`DestDesc::builder`].
+#[derive(Debug, Clone, Copy, typed_builder::TypedBuilder, serde::Serialize, serde::Deserialize)]
+#[non_exhaustive]
+pub struct DestDesc {
+    /// The destination ID.
+    pub id: DestId,
+    /// The downlink ID, referring to one of the [additional links](crate::Config::links). Its
+    /// bandwidth is the rate of the destination's NIC.
+    pub downlink: LinkId,
+}
@@ -1,13 +1,15 @@
-use std::cmp;
+use std::{cmp, collections::hash_map::Entry};
 
 use rustc_hash::FxHashMap;
 
 use crate::{
-    flow::{Flow, FlowDesc},
+    cc::{CcInit, CcKind},
+    data::SourceStats,
+    flow::{CoflowId, Flow, FlowDesc, MAX_MLFQ_LEVELS},
     packet::Ack,
     port::QIndex,
     simulation::{event::EventList, Context},
-    time::Time,
+    time::{Delta, Time},
     units::{BitsPerSec, Bytes, Nanosecs},
     FlowId, Packet, Record,
 };
@@ -16,11 +18,15 @@ use super::bottleneck::BottleneckCmd;
 
 identifier!(SourceId);
 
-#[derive(Debug, Clone, typed_builder::TypedBuilder)]
+#[derive(Debug, typed_builder::TypedBuilder)]
 pub(crate) struct Source {
     pub(crate) id: SourceId,
     #[builder(setter(into))]
     pub(crate) delay2btl: Nanosecs,
+    #[builder(default)]
+    cc: Option<CcKind>,
+    #[builder(default)]
+    schedule: FlowSchedule,
 
     #[builder(setter(into))]
     link_rate: BitsPerSec,
@@ -37,6 +43,14 @@ pub(crate) struct Source {
     #[builder(default, setter(skip))]
     version: u128,
 
+    // PFC state: the time each paused queue class was paused
+    #[builder(default, setter(skip))]
+    paused: FxHashMap<QIndex, Time>,
+    #[builder(default, setter(skip))]
+    nr_pauses: usize,
+    #[builder(default, setter(skip))]
+    paused_for: Delta,
+
     #[builder(default, setter(skip))]
     pub(crate) records: Vec<Record>,
 }
@@ -47,8 +61,21 @@ impl Source {
         if version != self.version {
             return ctx.into_events();
         }
-        match self.flow_queue.next_packet(&ctx) {
+        match self
+            .flow_queue
+            .next_packet(self.schedule, &self.paused, &ctx)
+        {
             FlowQResult::Found { pkt } => {
+                self.arm_timer(pkt.flow_id, &mut ctx);
+                if let Some(info) = self.flow_info.get_mut(&pkt.flow_id) {
+                    if pkt.is_retx {
+                        info.retransmits += 1;
+                    }
+                    if pkt.qindex != info.queue {
+                        info.leave_queue(ctx.cur_time);
+                        info.queue = pkt.qindex;
+                    }
+                }
                 // Send the packet to the bottleneck
                 let bw_delta = self.link_rate.length(pkt.size).into_delta();
                 ctx.schedule(
@@ -76,47 +103,153 @@ impl Source {
     pub(crate) fn rcv_ack(&mut self, flow_id: FlowId, ack: Ack, mut ctx: Context) -> EventList {
         if let Some(flow) = self.flow_queue.get_flow_mut(flow_id) {
             flow.rcv_ack(ack, &ctx);
-            if !flow.is_win_bound() && flow.tnext < self.tnext {
-                let tnext = cmp::max(self.earliest_tnext, flow.tnext);
-                self.version += 1;
-                ctx.schedule(
-                    tnext.saturating_sub(ctx.cur_time),
-                    SourceCmd::new_try_send(self.id, self.version),
-                );
-                self.tnext = tnext;
-            }
+            self.flow_queue.refresh(flow_id);
+            self.wake_for(flow_id, &mut ctx);
+            self.arm_timer(flow_id, &mut ctx);
+        }
+        ctx.into_events()
+    }
+
+    #[must_use]
+    pub(crate) fn flow_timer(&mut self, flow_id: FlowId, mut ctx: Context) -> EventList {
+        if let Some(flow) = self.flow_queue.get_flow_mut(flow_id) {
+            flow.on_timer(&ctx);
+            self.flow_queue.refresh(flow_id);
+            self.wake_for(flow_id, &mut ctx);
+            self.arm_timer(flow_id, &mut ctx);
+        }
+        ctx.into_events()
+    }
+
+    #[must_use]
+    pub(crate) fn pause(&mut self, qindex: QIndex, ctx: Context) -> EventList {
+        if let Entry::Vacant(e) = self.paused.entry(qindex) {
+            e.insert(ctx.cur_time);
+            self.nr_pauses += 1;
         }
         ctx.into_events()
     }
 
     #[must_use]
-    pub(crate) fn flow_arrive(&mut self, desc: FlowDesc, ctx: Context) -> EventList {
+    pub(crate) fn resume(&mut self, qindex: QIndex, mut ctx: Context) -> EventList {
+        let Some(since) = self.paused.remove(&qindex) else {
+            return ctx.into_events();
+        };
+        self.paused_for = self.paused_for + (ctx.cur_time - since);
+        // Flows in the resumed class may be able to send before the source's next wake-up time
+        if ctx.cur_time < self.tnext {
+            let tnext = cmp::max(self.earliest_tnext, ctx.cur_time);
+            self.version += 1;
+            ctx.schedule(
+                tnext - ctx.cur_time,
+                SourceCmd::new_try_send(self.id, self.version),
+            );
+            self.tnext = tnext;
+        }
+        ctx.into_events()
+    }
+
+    // Returns the source's PFC statistics as of `now`, counting classes that are still paused.
+    pub(crate) fn stats(&self, now: Time) -> SourceStats {
+        let paused_for = self
+            .paused
+            .values()
+            .fold(self.paused_for, |acc, &since| acc + (now - since));
+        SourceStats {
+            id: self.id,
+            nr_pauses: self.nr_pauses,
+            paused_for: paused_for.into_ns(),
+        }
+    }
+
+    // Reschedules the source if the flow can send earlier than the source's next wake-up time.
+    fn wake_for(&mut self, flow_id: FlowId, ctx: &mut Context) {
+        let Some(flow) = self.flow_queue.get_flow_mut(flow_id) else {
+            return;
+        };
+        if flow.has_data() && !flow.is_win_bound() && flow.tnext < self.tnext {
+            let tnext = cmp::max(self.earliest_tnext, flow.tnext);
+            self.version += 1;
+            ctx.schedule(
+                tnext.saturating_sub(ctx.cur_time),
+                SourceCmd::new_try_send(self.id, self.version),
+            );
+            self.tnext = tnext;
+        }
+    }
+
+    // Schedules a timer event if the flow's congestion controller requests one.
+    fn arm_timer(&mut self, flow_id: FlowId, ctx: &mut Context) {
+        let Some(flow) = self.flow_queue.get_flow_mut(flow_id) else {
+            return;
+        };
+        if let Some(t) = flow.arm_timer() {
+            ctx.schedule(
+                t.saturating_sub(ctx.cur_time),
+                SourceCmd::new_flow_timer(self.id, flow_id),
+            );
+        }
+    }
+
+    #[must_use]
+    pub(crate) fn flow_arrive(&mut self, desc: FlowDesc, mut ctx: Context) -> EventList {
         let btl2dst = desc.delay2dst - self.delay2btl;
+        let link_rates = desc
+            .links()
+            .iter()
+            .map(|id| ctx.link_bandwidths[id])
+            .collect();
         let info = FlowInfo {
             id: desc.id,
+            coflow: desc.coflow,
             size: desc.size,
+            deadline: desc.deadline,
             start: desc.start,
             qindex: desc.qindex,
             src2btl: self.delay2btl,
             btl2dst: desc.delay2dst - self.delay2btl,
             max_rate: self.link_rate,
+            link_rates,
+            retransmits: 0,
+            queue: desc.qindex,
+            queued_since: ctx.cur_time,
+            queue_times: [Nanosecs::ZERO; MAX_MLFQ_LEVELS],
         };
         self.flow_info.insert(info.id, info);
+        let initial_rate = desc
+            .initial_rate
+            .or(ctx.cc_params.initial_rate)
+            .map_or(self.link_rate, |rate| cmp::min(rate, self.link_rate));
+        let cc = desc.cc.or(self.cc).unwrap_or(ctx.cc_params.default).build(
+            CcInit {
+                now: ctx.cur_time,
+                max_rate: self.link_rate,
+                rate: initial_rate,
+                window: ctx.cc_params.window,
+                base_rtt: desc.delay2dst.scale_by(2.0),
+                nr_hops: desc.links().len(),
+                sz_pktmax: ctx.sz_pktmax,
+                size: desc.size,
+                deadline: desc.deadline.map(|d| (desc.start + d).into_time()),
+            },
+            &ctx.cc_params,
+        );
         let flow = Flow::builder()
             .id(desc.id)
             .source(desc.source)
+            .link(desc.links()[0])
             .qindex(desc.qindex)
             .size(desc.size)
-            .rate(self.link_rate)
-            .max_rate(self.link_rate)
-            .tnext(ctx.cur_time)
+            .priority(desc.priority)
             .src2btl(self.delay2btl)
             .btl2dst(btl2dst)
-            .window(ctx.window)
-            .gain(ctx.dctcp_gain)
-            .additive_inc(ctx.dctcp_ai)
+            .cc(cc)
+            .ss_window(desc.initial_window.or(ctx.cc_params.initial_window))
+            .tnext(ctx.cur_time)
+            .recovery(ctx.loss_recovery)
             .build();
-        self.flow_queue.add_flow(flow);
+        self.flow_queue.add_flow(flow, desc.weight.unwrap_or(1));
+        self.arm_timer(desc.id, &mut ctx);
         if self.earliest_tnext <= ctx.cur_time && ctx.cur_time < self.tnext {
             self.version += 1;
             self.try_send(self.version, ctx)
@@ -126,21 +259,28 @@ impl Source {
     }
 
     #[must_use]
+    #[allow(clippy::obfuscated_if_else)]
     pub(crate) fn flow_depart(&mut self, flow_id: FlowId, ctx: Context) -> EventList {
-        let flow = self
+        let mut flow = self
             .flow_info
             .remove(&flow_id)
             .expect("missing flow record");
-        // Compute the ideal FCT
+        // Compute the ideal FCT. The first packet is stored and forwarded at every hop, and the
+        // rest are pipelined behind it at the slowest link's rate.
         let bw_hop1 = flow.max_rate;
-        let bw_hop2 = ctx.btl_bandwidth;
-        let bw_min = cmp::min(bw_hop1, bw_hop2);
+        let bw_min = flow
+            .link_rates
+            .iter()
+            .fold(bw_hop1, |acc, &bw| cmp::min(acc, bw));
         let sz_head_ = cmp::min(ctx.sz_pktmax, flow.size);
         let sz_head = (sz_head_ != Bytes::ZERO)
             .then(|| sz_head_ + ctx.sz_pkthdr)
             .unwrap_or(Bytes::ZERO);
         let sz_rest_ = flow.size - sz_head_;
-        let head_delay = bw_hop1.length(sz_head) + bw_hop2.length(sz_head);
+        let head_delay = flow
+            .link_rates
+            .iter()
+            .fold(bw_hop1.length(sz_head), |acc, bw| acc + bw.length(sz_head));
         let rest_delay = {
             let nr_full_pkts = sz_rest_.into_usize() / ctx.sz_pktmax.into_usize();
             let sz_full_pkt = ctx.sz_pktmax + ctx.sz_pkthdr;
@@ -153,6 +293,8 @@ impl Source {
         let prop_delay = flow.src2btl + flow.btl2dst;
         let ideal = head_delay + rest_delay + prop_delay;
 
+        flow.leave_queue(ctx.cur_time);
+
         // Store the flow's FCT record
         let record = Record {
             id: flow.id,
@@ -161,13 +303,18 @@ impl Source {
             qindex: flow.qindex,
             fct: ctx.cur_time.into_ns() - flow.start,
             ideal,
+            retransmits: flow.retransmits,
+            drops: 0,
+            queue_times: flow.queue_times,
+            coflow: flow.coflow,
+            deadline: flow.deadline,
         };
         self.records.push(record);
         ctx.into_events()
     }
 }
 
-#[derive(Debug, Copy, Clone, derive_new::new)]
+#[derive(Debug, Clone, derive_new::new)]
 pub(crate) enum SourceCmd {
     TrySend {
         id: SourceId,
@@ -186,40 +333,86 @@ pub(crate) enum SourceCmd {
         source: SourceId,
         flow: FlowId,
     },
+    FlowTimer {
+        source: SourceId,
+        flow: FlowId,
+    },
+    Pause {
+        source: SourceId,
+        qindex: QIndex,
+    },
+    Resume {
+        source: SourceId,
+        qindex: QIndex,
+    },
 }
 
-#[derive(Debug, Default, Clone, derive_new::new)]
+#[derive(Debug, Default, derive_new::new)]
 struct FlowQ {
     #[new(default)]
     members: FxHashMap<FlowId, Flow>,
+    #[new(default)]
+    slots: FxHashMap<FlowId, Slot>,
 
     #[new(default)]
     order: Vec<FlowId>,
     rr_next: usize,
+
+    #[new(default)]
+    nr_arrivals: u64,
+    // The virtual start time of the packet most recently sent under weighted scheduling
+    #[new(default)]
+    vtime: f64,
+}
+
+// A flow's scheduling state
+#[derive(Debug, Clone, Copy)]
+struct Slot {
+    arrival: u64,
+    weight: u64,
+    // The virtual time at which the flow's next packet starts under weighted scheduling
+    vtime: f64,
 }
 
 impl FlowQ {
-    fn next_packet(&mut self, ctx: &Context) -> FlowQResult {
+    // Flows in paused queue classes are skipped.
+    fn next_packet(
+        &mut self,
+        schedule: FlowSchedule,
+        paused: &FxHashMap<QIndex, Time>,
+        ctx: &Context,
+    ) -> FlowQResult {
         if self.order.is_empty() {
             return FlowQResult::Empty;
         }
         let mut min_viable_tnext = None;
+        // The index of the flow to send from, along with its rank under `schedule`
+        let mut best: Option<(usize, f64)> = None;
         let nr_flows = self.order.len();
         for i in 0..nr_flows {
             let idx = (i + self.rr_next) % nr_flows;
             let id = self.order[idx];
-            let flow = self.members.get_mut(&id).unwrap();
+            let flow = &self.members[&id];
+            if paused.contains_key(&flow.next_qindex(ctx)) {
+                continue;
+            }
             match (flow.is_rate_bound(ctx.cur_time), flow.is_win_bound()) {
                 (false, false) => {
-                    // This flow can send, so there's nothing left to do but update the order.
-                    let pkt = flow.next_packet(ctx);
-                    let id = flow.id;
-                    if flow.bytes_left() == Bytes::ZERO {
-                        self.order.remove(idx);
-                        self.members.remove(&id);
+                    // This flow can send. Ties go to the flow closest to the round-robin
+                    // position.
+                    let slot = &self.slots[&id];
+                    let rank = match schedule {
+                        FlowSchedule::RoundRobin => {
+                            best = Some((idx, 0.0));
+                            break;
+                        }
+                        FlowSchedule::Fifo => slot.arrival as f64,
+                        FlowSchedule::Srpt => flow.bytes_left().into_f64(),
+                        FlowSchedule::Weighted => slot.vtime,
+                    };
+                    if best.is_none_or(|(_, r)| rank < r) {
+                        best = Some((idx, rank));
                     }
-                    self.rr_next = idx + 1;
-                    return FlowQResult::Found { pkt };
                 }
                 (true, false) => {
                     // This flow can definitely send later because it isn't window-bound, and it
@@ -232,6 +425,22 @@ impl FlowQ {
                 _ => continue,
             }
         }
+        if let Some((idx, _)) = best {
+            // There's nothing left to do but update the order. Flows with nothing left to send
+            // leave the order but remain members until all of their data has been acknowledged.
+            let id = self.order[idx];
+            let flow = self.members.get_mut(&id).unwrap();
+            let pkt = flow.next_packet(ctx);
+            if !flow.has_data() {
+                flow.is_queued = false;
+                self.order.remove(idx);
+            }
+            self.rr_next = idx + 1;
+            let slot = self.slots.get_mut(&id).unwrap();
+            self.vtime = slot.vtime;
+            slot.vtime += pkt.size.into_f64() / slot.weight as f64;
+            return FlowQResult::Found { pkt };
+        }
         match min_viable_tnext {
             Some(tnext) => {
                 assert!(tnext > ctx.cur_time); // otherwise, we would've sent it
@@ -241,43 +450,96 @@ impl FlowQ {
         }
     }
 
-    fn add_flow(&mut self, flow: Flow) {
+    fn add_flow(&mut self, flow: Flow, weight: u64) {
         let id = flow.id;
         assert!(!self.members.contains_key(&id));
         self.order.push(id);
         self.members.insert(id, flow);
+        self.slots.insert(
+            id,
+            Slot {
+                arrival: self.nr_arrivals,
+                weight,
+                vtime: self.vtime,
+            },
+        );
+        self.nr_arrivals += 1;
     }
 
     fn get_flow_mut(&mut self, flow_id: FlowId) -> Option<&mut Flow> {
         self.members.get_mut(&flow_id)
     }
+
+    // Removes the flow once it is complete, and puts it back in the order if it has something to
+    // (re)send.
+    fn refresh(&mut self, flow_id: FlowId) {
+        let Some(flow) = self.members.get_mut(&flow_id) else {
+            return;
+        };
+        if flow.is_complete() {
+            if flow.is_queued {
+                self.order.retain(|&id| id != flow_id);
+            }
+            self.members.remove(&flow_id);
+            self.slots.remove(&flow_id);
+        } else if flow.has_data() && !flow.is_queued {
+            flow.is_queued = true;
+            self.order.push(flow_id);
+            // A flow that was idle gets no credit for the time it didn't send
+            let slot = self.slots.get_mut(&flow_id).unwrap();
+            slot.vtime = slot.vtime.max(self.vtime);
+        }
+    }
 }
 
+// Only returned by value, so boxing the packet would cost an allocation per packet for nothing
+#[allow(clippy::large_enum_variant)]
 #[derive(Debug)]
 enum FlowQResult {
     // The next packet to send
     Found { pkt: Packet },
     // Rate-bound, but not window-bound
     RateBound { tnext: Time },
-    // Window-bound
+    // Window-bound or paused
     WinBound,
     // No flows in the flow queue
     Empty,
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone)]
 struct FlowInfo {
     id: FlowId,
+    coflow: Option<CoflowId>,
     size: Bytes,
+    deadline: Option<Nanosecs>,
     start: Nanosecs,
     qindex: QIndex,
     src2btl: Nanosecs,
     btl2dst: Nanosecs,
     max_rate: BitsPerSec,
+    // The bandwidths of the bottleneck links on the flow's path
+    link_rates: Vec<BitsPerSec>,
+    retransmits: usize,
+    // The queue the flow last sent into and the time it entered it. A flow stays in a queue
+    // until it first sends into another.
+    queue: QIndex,
+    queued_since: Time,
+    // The time spent at each MLFQ level before entering the current queue
+    queue_times: [Nanosecs; MAX_MLFQ_LEVELS],
 }
 
-/// A source configuration.
+impl FlowInfo {
+    // Charges the time spent in the current queue to its MLFQ level
+    fn leave_queue(&mut self, now: Time) {
+        let level = self.queue.inner() - self.qindex.inner();
+        self.queue_times[level] += (now - self.queued_since).into_ns();
+        self.queued_since = now;
+    }
+}
+
+/// A source configuration, built with [`SourceDesc::builder`].
 #[derive(Debug, Clone, Copy, typed_builder::TypedBuilder, serde::Serialize, serde::Deserialize)]
+#[non_exhaustive]
 pub struct SourceDesc {
     /// The source ID.
     pub id: SourceId,
@@ -287,4 +549,29 @@ pub struct SourceDesc {
     /// The rate of the link connecting the source to the bottleneck.
     #[builder(setter(into))]
     pub link_rate: BitsPerSec,
+    /// The congestion control algorithm used by this source's flows, overriding the global
+    /// setting.
+    #[builder(default, setter(strip_option))]
+    #[serde(default)]
+    pub cc: Option<CcKind>,
+    /// The order in which the source serves its active flows.
+    #[builder(default)]
+    #[serde(default)]
+    pub schedule: FlowSchedule,
+}
+
+/// A policy for choosing which of a source's flows sends next. Flows that are paused, or bound
+/// by their rate or window, are passed over.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum FlowSchedule {
+    /// Round robin, one packet per flow.
+    #[default]
+    RoundRobin,
+    /// First in, first out, by flow arrival.
+    Fifo,
+    /// Shortest remaining processing time, by bytes left to send.
+    Srpt,
+    /// Start-time fair queueing, sharing the source's link in proportion to
+    /// [flow weights](FlowDesc::weight).
+    Weighted,
 }
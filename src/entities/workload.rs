@@ -1,25 +1,55 @@
-use std::collections::VecDeque;
+use std::{cmp, collections::VecDeque};
+
+use rustc_hash::FxHashMap;
 
 use crate::{
-    flow::FlowDesc,
-    simulation::{event::EventList, Context}, units::Bytes,
+    flow::{FlowDesc, FlowId},
+    simulation::{event::EventList, Context},
+    units::Bytes,
 };
 
 use super::source::SourceCmd;
 
-#[derive(Debug, Clone, derive_new::new)]
+#[derive(Debug, Clone)]
 pub(crate) struct Workload {
+    // Flows that start at fixed times, in order of start time
     flows: VecDeque<FlowDesc>,
+    // Flows that start once other flows complete, along with their number of incomplete
+    // dependencies
+    waiting: FxHashMap<FlowId, (FlowDesc, usize)>,
+    // The flows waiting on each flow
+    dependents: FxHashMap<FlowId, Vec<FlowId>>,
 }
 
 impl Workload {
+    pub(crate) fn new(flows: Vec<FlowDesc>) -> Self {
+        let mut dependents = FxHashMap::<_, Vec<_>>::default();
+        for flow in &flows {
+            for &dep in &flow.depends_on {
+                dependents.entry(dep).or_default().push(flow.id);
+            }
+        }
+        let (waiting, flows): (Vec<_>, Vec<_>) = flows
+            .into_iter()
+            .partition(|flow| !flow.depends_on.is_empty());
+        let waiting = waiting
+            .into_iter()
+            .map(|flow| {
+                let nr_deps = flow.depends_on.len();
+                (flow.id, (flow, nr_deps))
+            })
+            .collect();
+        Self {
+            flows: flows.into(),
+            waiting,
+            dependents,
+        }
+    }
+
     #[must_use]
     pub(crate) fn step(&mut self, mut ctx: Context) -> EventList {
         if let Some(flow) = self.flows.pop_front() {
-            if flow.size > Bytes::ZERO {
-                let delta = flow.start.into_time() - ctx.cur_time;
-                ctx.schedule(delta, SourceCmd::new_flow_arrive(flow.source, flow));
-            }
+            self.start(flow, &mut ctx);
 
             // Reschedule the next flow arrival
             if let Some(&FlowDesc {
@@ -32,9 +62,39 @@ impl Workload {
         }
         ctx.into_events()
     }
+
+    // Releases the flows whose last incomplete dependency is `id`.
+    #[must_use]
+    pub(crate) fn flow_depart(&mut self, id: FlowId, mut ctx: Context) -> EventList {
+        for dependent in self.dependents.remove(&id).unwrap_or_default() {
+            let (_, nr_deps) = self
+                .waiting
+                .get_mut(&dependent)
+                .expect("missing dependent flow");
+            *nr_deps -= 1;
+            if *nr_deps == 0 {
+                let (mut flow, _) = self.waiting.remove(&dependent).unwrap();
+                flow.start = cmp::max(flow.start, ctx.cur_time.into_ns() + flow.think_time);
+                self.start(flow, &mut ctx);
+            }
+        }
+        ctx.into_events()
+    }
+
+    // Schedules the arrival of `flow` at its start time. Empty flows never arrive, so they
+    // complete at their start time if other flows depend on them.
+    fn start(&self, flow: FlowDesc, ctx: &mut Context) {
+        let delta = flow.start.into_time() - ctx.cur_time;
+        if flow.size > Bytes::ZERO {
+            ctx.schedule(delta, SourceCmd::new_flow_arrive(flow.source, flow));
+        } else if self.dependents.contains_key(&flow.id) {
+            ctx.schedule(delta, WorkloadCmd::new_flow_depart(flow.id));
+        }
+    }
 }
 
 #[derive(Debug, Copy, Clone, derive_new::new)]
 pub(crate) enum WorkloadCmd {
     Step,
+    FlowDepart(FlowId),
 }
@@ -1,60 +1,75 @@
-use std::cmp;
+use std::{cmp, collections::BTreeMap};
 
 use crate::{
+    cc::{AckInfo, CcKind, CongestionControl},
     packet::Ack,
     port::QIndex,
+    receiver,
     simulation::Context,
     time::Time,
-    units::{BitsPerSec, Bytes, Nanosecs},
-    Packet, SourceId,
+    units::{BitsPerSec, Bytes, Millisecs, Nanosecs},
+    DestId, LinkId, Packet, SourceId,
 };
 
 identifier!(FlowId);
+identifier!(CoflowId);
 
-#[derive(Debug, Clone, typed_builder::TypedBuilder)]
+#[derive(Debug, typed_builder::TypedBuilder)]
 pub(crate) struct Flow {
     pub(crate) id: FlowId,
     source: SourceId,
+    // The first bottleneck link on the flow's path
+    link: LinkId,
     qindex: QIndex,
     size: Bytes,
+    #[builder(default)]
+    priority: Option<u64>,
     #[builder(setter(into))]
     src2btl: Nanosecs,
     #[builder(setter(into))]
     btl2dst: Nanosecs,
 
-    // Rate management
-    #[builder(setter(into))]
-    rate: BitsPerSec,
-    #[builder(default = BitsPerSec::new(1_000_000_000))]
-    min_rate: BitsPerSec,
-    #[builder(setter(into))]
-    max_rate: BitsPerSec,
+    // Rate and window management
+    cc: Box<dyn CongestionControl>,
+    // The slow-start window, which caps the congestion control window until the flow leaves slow
+    // start
+    #[builder(default)]
+    ss_window: Option<Bytes>,
     pub(crate) tnext: Time,
-
-    // Window management
-    #[builder(setter(into))]
-    window: Bytes,
     #[builder(default, setter(skip))]
     snd_nxt: Bytes,
     #[builder(default, setter(skip))]
     snd_una: Bytes,
+    #[builder(default, setter(skip))]
+    snd_max: Bytes,
+    // The number of payload bytes sent, including retransmissions
+    #[builder(default, setter(skip))]
+    sz_sent: Bytes,
 
-    // DCTCP
-    #[builder(default = 1.0, setter(skip))]
-    alpha: f64,
-    gain: f64,
-    #[builder(setter(into))]
-    additive_inc: BitsPerSec,
+    // Loss recovery, enabled only if the bottleneck can drop packets
+    #[builder(default)]
+    recovery: Option<LossRecovery>,
+    #[builder(default, setter(skip))]
+    sacked: BTreeMap<Bytes, Bytes>,
     #[builder(default, setter(skip))]
-    last_update_seq: Bytes,
+    recovery_point: Option<Bytes>,
     #[builder(default, setter(skip))]
-    batch_size: usize,
+    retx_nxt: Bytes,
     #[builder(default, setter(skip))]
-    marked_count: usize,
+    srtt: Option<Nanosecs>,
     #[builder(default, setter(skip))]
-    ca_state: CaState,
+    rttvar: Nanosecs,
     #[builder(default, setter(skip))]
-    high_seq: Bytes,
+    rto_deadline: Option<Time>,
+    #[builder(default, setter(skip))]
+    rto_backoff: u32,
+
+    // The earliest pending timer event, if any
+    #[builder(default, setter(skip))]
+    timer_armed: Option<Time>,
+    // Whether the flow is in its flow queue's scheduling order
+    #[builder(default = true, setter(skip))]
+    pub(crate) is_queued: bool,
 }
 
 impl Flow {
@@ -62,13 +77,27 @@ impl Flow {
         self.size.saturating_sub(self.snd_nxt)
     }
 
+    // Whether the flow has new data or a hole to retransmit
+    pub(crate) fn has_data(&self) -> bool {
+        self.bytes_left() > Bytes::ZERO || self.next_hole().is_some()
+    }
+
+    pub(crate) fn is_complete(&self) -> bool {
+        self.snd_una >= self.size
+    }
+
     pub(crate) fn on_the_fly(&self) -> Bytes {
-        self.snd_nxt - self.snd_una
+        let sacked = self
+            .sacked
+            .iter()
+            .map(|(&s, &e)| e.saturating_sub(cmp::max(s, self.snd_una)))
+            .sum::<Bytes>();
+        (self.snd_nxt - self.snd_una).saturating_sub(sacked)
     }
 
     pub(crate) fn variable_window(&self) -> Bytes {
-        self.window
-            .scale_by(BitsPerSec::frac(self.rate, self.max_rate))
+        let window = self.cc.window();
+        self.ss_window.map_or(window, |ss| cmp::min(ss, window))
     }
 
     pub(crate) fn usable_window(&self) -> Bytes {
@@ -83,93 +112,343 @@ impl Flow {
         self.usable_window() == Bytes::ZERO
     }
 
+    // The queue index of the next packet. Flows are demoted through the queues as they send
+    // more bytes if an MLFQ classifier is configured. Retransmissions count towards the bytes
+    // sent, so a flow that rewinds after a loss is never promoted back.
+    pub(crate) fn next_qindex(&self, ctx: &Context) -> QIndex {
+        match &ctx.mlfq {
+            Some(mlfq) => mlfq.classify(self.qindex, self.sz_sent),
+            None => self.qindex,
+        }
+    }
+
     pub(crate) fn next_packet(&mut self, ctx: &Context) -> Packet {
-        assert!(self.bytes_left() > Bytes::ZERO);
+        assert!(self.has_data());
         assert!(self.usable_window() > Bytes::ZERO);
 
+        // Holes reported by SACKs are retransmitted before any new data is sent
+        let qindex = self.next_qindex(ctx);
+        let hole = self.next_hole();
+        let (seq, sz_avail) = match hole {
+            Some((start, end)) => (start, end - start),
+            None => (self.snd_nxt, self.bytes_left()),
+        };
+
         // Amount to send is capped by the remaining flow size, the maximum packet size, and the
         // usable window size.
-        let sz_payload = cmp::min(self.bytes_left(), ctx.sz_pktmax);
+        let sz_payload = cmp::min(sz_avail, ctx.sz_pktmax);
         let sz_payload = cmp::min(sz_payload, self.usable_window());
-        self.snd_nxt += sz_payload;
+        if hole.is_some() {
+            self.retx_nxt = seq + sz_payload;
+        } else {
+            self.snd_nxt += sz_payload;
+        }
+        self.sz_sent += sz_payload;
+        let is_retx = seq < self.snd_max;
+        self.snd_max = cmp::max(self.snd_max, self.snd_nxt);
         let sz_pkt = sz_payload + ctx.sz_pkthdr;
-        let rate_delta = self.rate.length(sz_pkt).into_delta();
+        let rate_delta = self.cc.rate().length(sz_pkt).into_delta();
         self.tnext = ctx.cur_time + rate_delta;
+        self.cc.on_send(sz_pkt, ctx.cur_time);
+        if self.recovery.is_some() && self.rto_deadline.is_none() {
+            self.rto_deadline = Some(ctx.cur_time + self.rto().into_delta());
+        }
 
-        let is_last = self.bytes_left() == Bytes::ZERO;
+        let is_last = seq + sz_payload == self.size;
         Packet::builder()
             .flow_id(self.id)
             .source_id(self.source)
-            .qindex(self.qindex)
+            .link(self.link)
+            .qindex(qindex)
+            .seq(seq)
             .size(sz_pkt)
             .is_last(is_last)
+            .is_retx(is_retx)
+            .priority(
+                self.priority
+                    .unwrap_or_else(|| (self.size - seq).into_u64()),
+            )
             .src2btl(self.src2btl)
             .btl2dst(self.btl2dst)
+            .sent_at(ctx.cur_time)
             .build()
     }
 
     // TODO: update `tnext`
     pub(crate) fn rcv_ack(&mut self, ack: Ack, ctx: &Context) {
-        self.snd_una += ack.nr_bytes;
-        let mut new_batch = false;
-        if ack.marked {
-            self.marked_count += 1;
+        let rtt = (ctx.cur_time - ack.sent_at).into_ns();
+        let nr_bytes = ack.ackno.saturating_sub(self.snd_una);
+        if nr_bytes > Bytes::ZERO {
+            self.snd_una = ack.ackno;
+            self.snd_nxt = cmp::max(self.snd_nxt, self.snd_una);
         }
-        // Update alpha
-        if self.snd_una > self.last_update_seq {
-            new_batch = true;
-            if self.last_update_seq == Bytes::ZERO {
-                // First RTT
-                self.batch_size = Packet::min_count_in(self.snd_nxt, ctx.sz_pktmax);
-            } else {
-                let frac = (self.marked_count as f64 / self.batch_size as f64).clamp(0.0, 1.0);
-                self.alpha = (1.0 - self.gain) * self.alpha + self.gain * frac;
-                self.marked_count = 0;
-                self.batch_size = Packet::min_count_in(self.snd_nxt - self.snd_una, ctx.sz_pktmax);
+        if let Some(recovery) = self.recovery {
+            self.update_rtt(rtt);
+            if nr_bytes > Bytes::ZERO {
+                self.rto_backoff = 0;
+                self.rto_deadline =
+                    (self.snd_una < self.snd_max).then(|| ctx.cur_time + self.rto().into_delta());
+                self.sacked.retain(|_, &mut e| e > self.snd_una);
             }
-            self.last_update_seq = self.snd_nxt;
+            match recovery.mode {
+                Retransmission::GoBackN if ack.nack => {
+                    self.snd_nxt = self.snd_una;
+                    self.on_loss(ctx.cur_time);
+                }
+                Retransmission::Selective => {
+                    if let Some((start, end)) = ack.sack {
+                        receiver::merge_range(&mut self.sacked, start, end);
+                        if self.recovery_point.is_none() {
+                            // Holes below `retx_nxt` were already resent in an earlier
+                            // recovery episode
+                            self.recovery_point = Some(self.snd_max);
+                            self.retx_nxt = cmp::max(self.retx_nxt, self.snd_una);
+                            self.on_loss(ctx.cur_time);
+                        }
+                    }
+                    if self.recovery_point.is_some_and(|p| self.snd_una >= p) {
+                        self.recovery_point = None;
+                    }
+                }
+                _ => (),
+            }
+        }
+        self.cc.on_ack(&AckInfo {
+            now: ctx.cur_time,
+            nr_bytes,
+            marked: ack.marked_bytes > Bytes::ZERO,
+            marked_bytes: ack.marked_bytes,
+            rtt,
+            snd_una: self.snd_una,
+            snd_nxt: self.snd_nxt,
+            sz_pktmax: ctx.sz_pktmax,
+            int: &ack.int,
+        });
+        // Slow start doubles the window every round trip until the first congestion signal or
+        // until the congestion control window is reached
+        if let Some(ss) = self.ss_window.as_mut() {
+            *ss += nr_bytes;
+            if ack.marked_bytes > Bytes::ZERO || *ss >= self.cc.window() {
+                self.ss_window = None;
+            }
+        }
+    }
+
+    fn on_loss(&mut self, now: Time) {
+        self.ss_window = None;
+        self.cc.on_loss(now);
+    }
+
+    // Returns the time of a timer event that needs to be scheduled, if any. Stale timer events are
+    // allowed to fire; `on_timer` ignores them.
+    pub(crate) fn arm_timer(&mut self) -> Option<Time> {
+        let t = match (self.cc.next_timer(), self.rto_deadline) {
+            (Some(a), Some(b)) => cmp::min(a, b),
+            (a, b) => a.or(b)?,
+        };
+        if self.timer_armed.is_some_and(|armed| armed <= t) {
+            return None;
+        }
+        self.timer_armed = Some(t);
+        Some(t)
+    }
+
+    pub(crate) fn on_timer(&mut self, ctx: &Context) {
+        if self.timer_armed == Some(ctx.cur_time) {
+            self.timer_armed = None;
+        }
+        if self.cc.next_timer().is_some_and(|t| t <= ctx.cur_time) {
+            self.cc.on_timer(ctx.cur_time);
+        }
+        if self.rto_deadline.is_some_and(|t| t <= ctx.cur_time) {
+            // Retransmission timeout: resend everything that hasn't been acknowledged. The
+            // receiver still holds any out-of-order data, so its cumulative ACKs skip ahead.
+            self.snd_nxt = self.snd_una;
+            self.sacked.clear();
+            self.recovery_point = None;
+            self.rto_backoff = cmp::min(self.rto_backoff + 1, MAX_RTO_BACKOFF);
+            self.rto_deadline = Some(ctx.cur_time + self.rto().into_delta());
+            self.on_loss(ctx.cur_time);
         }
+    }
 
-        if self.ca_state == CaState::One && self.snd_una > self.high_seq {
-            self.ca_state = CaState::Zero;
+    // Returns the first gap between SACKed ranges at or after `retx_nxt`.
+    fn next_hole(&self) -> Option<(Bytes, Bytes)> {
+        self.recovery_point?;
+        let mut start = cmp::max(self.retx_nxt, self.snd_una);
+        for (&s, &e) in &self.sacked {
+            if e <= start {
+                continue;
+            }
+            if s > start {
+                return Some((start, s));
+            }
+            start = e;
         }
-        if self.ca_state == CaState::Zero {
-            if ack.marked {
-                // Reduce rate
-                let new_rate = self.rate.scale_by(1.0 - self.alpha / 2.0);
-                self.rate = cmp::max(self.min_rate, new_rate);
-                self.ca_state = CaState::One;
-                self.high_seq = self.snd_nxt;
+        None
+    }
+
+    // RFC 6298
+    fn update_rtt(&mut self, rtt: Nanosecs) {
+        match self.srtt {
+            None => {
+                self.srtt = Some(rtt);
+                self.rttvar = rtt.scale_by(0.5);
             }
-            if new_batch {
-                let new_rate = self.rate.saturating_add(self.additive_inc);
-                self.rate = cmp::min(self.max_rate, new_rate);
+            Some(srtt) => {
+                let err = if srtt > rtt { srtt - rtt } else { rtt - srtt };
+                self.rttvar = self.rttvar.scale_by(0.75) + err.scale_by(0.25);
+                self.srtt = Some(srtt.scale_by(0.875) + rtt.scale_by(0.125));
             }
         }
     }
+
+    fn rto(&self) -> Nanosecs {
+        let recovery = self.recovery.expect("loss recovery is disabled");
+        let rto = match self.srtt {
+            Some(srtt) => cmp::max(recovery.rto_min, srtt + self.rttvar.scale_by(4.0)),
+            None => recovery.rto_min,
+        };
+        rto.scale_by(f64::from(1_u32 << self.rto_backoff))
+    }
+}
+
+const MAX_RTO_BACKOFF: u32 = 6;
+
+// The number of MLFQ levels whose times are kept in flow records
+pub(crate) const MAX_MLFQ_LEVELS: usize = 8;
+
+// A PIAS-style multi-level feedback queue classifier.
+#[derive(Debug)]
+pub(crate) struct Mlfq {
+    // PRECONDITION: Thresholds must be strictly increasing
+    pub(crate) thresholds: Vec<Bytes>,
+    pub(crate) nr_queues: usize,
+}
+
+impl Mlfq {
+    // Returns the queue for a flow that starts in `base` and has sent `sent` bytes. The flow
+    // moves down one queue for every threshold it has crossed, stopping at the last queue.
+    pub(crate) fn classify(&self, base: QIndex, sent: Bytes) -> QIndex {
+        let nr_crossed = self.thresholds.partition_point(|&t| t <= sent);
+        let idx = cmp::min(base.inner() + nr_crossed, self.nr_queues - 1);
+        QIndex::new(cmp::max(idx, base.inner()))
+    }
+}
+
+/// Loss recovery parameters, used when the bottleneck buffers are finite.
+#[derive(Debug, Clone, Copy, typed_builder::TypedBuilder, serde::Serialize, serde::Deserialize)]
+pub struct LossRecovery {
+    /// The retransmission scheme.
+    #[builder(default)]
+    pub mode: Retransmission,
+    /// The minimum retransmission timeout.
+    #[builder(default = Millisecs::new(1).into_us().into_ns(), setter(into))]
+    pub rto_min: Nanosecs,
+}
+
+impl Default for LossRecovery {
+    fn default() -> Self {
+        Self::builder().build()
+    }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, derivative::Derivative)]
-#[derivative(Default)]
-enum CaState {
-    #[derivative(Default)]
-    Zero,
-    One,
+/// A retransmission scheme.
+#[derive(
+    Debug, Default, Clone, Copy, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize,
+)]
+pub enum Retransmission {
+    /// The receiver discards out-of-order packets and NACKs the first one; the sender then
+    /// resends everything from the first unacknowledged byte.
+    #[default]
+    GoBackN,
+    /// The receiver buffers out-of-order packets and SACKs them; the sender only resends the
+    /// holes.
+    Selective,
 }
 
 /// A flow configuration.
-#[derive(Debug, Clone, Copy, serde::Serialize, serde::Deserialize)]
+///
+/// Fields may be added in later versions, so flows are built with [`FlowDesc::builder`] or
+/// deserialized rather than written as struct literals.
+#[derive(Debug, Clone, typed_builder::TypedBuilder, serde::Serialize, serde::Deserialize)]
+#[non_exhaustive]
 pub struct FlowDesc {
     /// The flow ID.
     pub id: FlowId,
     /// The originating source ID.
     pub source: SourceId,
     /// The queue index.
+    #[builder(default)]
     pub qindex: QIndex,
     /// The flow size.
+    #[builder(setter(into))]
     pub size: Bytes,
     /// The flow's start time.
+    #[builder(setter(into))]
     pub start: Nanosecs,
-    /// The propagation delay between the source and the destination.
+    /// The propagation delay between the source and the destination, including the delays
+    /// between the links on the flow's path.
+    #[builder(setter(into))]
     pub delay2dst: Nanosecs,
+    /// The priority of the flow's packets, used if the bottleneck schedules packets by priority.
+    /// Lower values are served first. Defaults to the flow's remaining size, as in pFabric.
+    #[builder(default, setter(strip_option))]
+    #[serde(default)]
+    pub priority: Option<u64>,
+    /// The congestion control algorithm, overriding the source's and the global setting.
+    #[builder(default, setter(strip_option))]
+    #[serde(default)]
+    pub cc: Option<CcKind>,
+    /// The bottleneck links traversed by the flow, in order. If empty, the flow only traverses
+    /// the top-level bottleneck.
+    #[builder(default)]
+    #[serde(default)]
+    pub path: Vec<LinkId>,
+    /// The destination, if any. The flow then traverses the destination's downlink after the
+    /// links on its path.
+    #[builder(default, setter(strip_option))]
+    #[serde(default)]
+    pub dst: Option<DestId>,
+    /// The flow's weight, used if its source schedules flows by weight. Defaults to one.
+    #[builder(default, setter(strip_option))]
+    #[serde(default)]
+    pub weight: Option<u64>,
+    /// The initial sending rate, overriding the global setting.
+    #[builder(default, setter(into, strip_option))]
+    #[serde(default)]
+    pub initial_rate: Option<BitsPerSec>,
+    /// The initial window, overriding the global setting. The flow starts in slow start if set.
+    #[builder(default, setter(into, strip_option))]
+    #[serde(default)]
+    pub initial_window: Option<Bytes>,
+    /// The flows that must complete before this flow starts. If nonempty, the flow starts at
+    /// `think_time` after the last of them completes, but no earlier than `start`.
+    #[builder(default)]
+    #[serde(default)]
+    pub depends_on: Vec<FlowId>,
+    /// The delay between the completion of the flow's dependencies and its start.
+    #[builder(default, setter(into))]
+    #[serde(default)]
+    pub think_time: Nanosecs,
+    /// The coflow the flow belongs to, if any. A coflow completes when all of its flows
+    /// complete.
+    #[builder(default, setter(strip_option))]
+    #[serde(default)]
+    pub coflow: Option<CoflowId>,
+    /// The flow's deadline, relative to its start, if any.
+    #[builder(default, setter(into, strip_option))]
+    #[serde(default)]
+    pub deadline: Option<Nanosecs>,
+}
+
+impl FlowDesc {
+    // The bottleneck links traversed by the flow, in order
+    pub(crate) fn links(&self) -> &[LinkId] {
+        if self.path.is_empty() {
+            &[LinkId::ZERO]
+        } else {
+            &self.path
+        }
+    }
 }
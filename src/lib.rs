@@ -1,27 +1,42 @@
 //! Minim is a custom single-link simulator built for the Parsimon project. Given a
 //! [bottleneck link configuration](Config), [congestion control parameters](Config)
-//! (only DCTCP is currently supported), a list of [sources](SourceDesc), and a list of
+//! (see [cc] for the supported algorithms), a list of [sources](SourceDesc), and a list of
 //! [flows](FlowDesc), Minim will output a list of [records](Record) via the [run] function.
+//! Flows can be generated synthetically with the [workload] module.
 
 #![warn(unreachable_pub, missing_debug_implementations, missing_docs)]
 
 #[macro_use]
 mod ident;
 
+pub mod cc;
 pub mod time;
 pub mod units;
+pub mod workload;
 
+pub(crate) mod aqm;
 pub(crate) mod data;
 pub(crate) mod driver;
 pub(crate) mod entities;
 pub(crate) mod flow;
 pub(crate) mod packet;
 pub(crate) mod port;
+pub(crate) mod receiver;
 pub(crate) mod simulation;
 
-pub use data::Record;
-pub use driver::{read_flows, run, Config, ConfigBuilder, ReadFlowsError};
-pub use entities::source::{SourceDesc, SourceId};
-pub use flow::{FlowDesc, FlowId};
-pub use packet::Packet;
-pub use port::QIndex;
+pub use aqm::{AqmKind, CoDelConfig, PieConfig};
+pub use data::{BackgroundStats, CoflowRecord, LinkStats, Output, QueueStats, Record, SourceStats};
+pub use driver::{
+    read_bandwidth_trace, read_flows, run, run_with_stats, Config, ConfigBuilder, ReadFlowsError,
+    ReadTraceError,
+};
+pub use entities::{
+    background::{BackgroundDesc, BackgroundId, TrafficPattern},
+    bottleneck::{AckPath, BandwidthTrace, LinkDesc, LinkId, PfcConfig, RedConfig},
+    destination::{DestDesc, DestId},
+    source::{FlowSchedule, SourceDesc, SourceId},
+};
+pub use flow::{CoflowId, FlowDesc, FlowId, LossRecovery, Retransmission};
+pub use packet::{IntHop, Packet};
+pub use port::{QIndex, SchedulerKind, SharedBuffer};
+pub use receiver::DelayedAck;
@@ -1,22 +1,46 @@
+use smallvec::SmallVec;
 use typed_builder::TypedBuilder;
 
 use crate::{
-    entities::source::SourceId,
+    entities::{background::BackgroundId, bottleneck::LinkId, source::SourceId},
     port::QIndex,
-    units::{Bytes, Nanosecs},
+    time::Time,
+    units::{BitsPerSec, Bytes, Nanosecs},
     FlowId,
 };
 
 /// A packet of data.
-#[derive(Debug, Default, Clone, Copy, TypedBuilder)]
+#[derive(Debug, Default, Clone, TypedBuilder)]
 pub struct Packet {
     pub(crate) flow_id: FlowId,
     pub(crate) source_id: SourceId,
     pub(crate) qindex: QIndex,
+    // The bottleneck link the packet is traveling to
+    pub(crate) link: LinkId,
+    // The offset of the first payload byte within the flow
+    pub(crate) seq: Bytes,
     pub(crate) size: Bytes,
     pub(crate) src2btl: Nanosecs,
     pub(crate) btl2dst: Nanosecs,
     pub(crate) is_last: bool,
+    pub(crate) is_retx: bool,
+    // The scheduling priority; lower values are served first
+    pub(crate) priority: u64,
+    pub(crate) sent_at: Time,
+    // Set by the bottleneck
+    #[builder(default)]
+    pub(crate) enqueued_at: Time,
+    #[builder(default)]
+    pub(crate) marked: bool,
+    // The telemetry stamped by each bottleneck crossed so far
+    #[builder(default)]
+    pub(crate) int: IntPath,
+    // Set for ACKs crossing a reverse link, which holds the ACK's contents under this key
+    #[builder(default)]
+    pub(crate) ack: Option<u64>,
+    // Set for background traffic, which belongs to no flow
+    #[builder(default)]
+    pub(crate) background: Option<BackgroundId>,
 }
 
 impl Packet {
@@ -33,8 +57,33 @@ impl Packet {
     }
 }
 
-#[derive(Debug, Clone, Copy, derive_new::new)]
+#[derive(Debug, Clone, derive_new::new)]
 pub(crate) struct Ack {
-    pub(crate) nr_bytes: Bytes,
-    pub(crate) marked: bool,
+    // The cumulative ACK number, i.e., the next byte expected by the receiver
+    pub(crate) ackno: Bytes,
+    // The range of an out-of-order packet buffered by the receiver
+    pub(crate) sack: Option<(Bytes, Bytes)>,
+    pub(crate) nack: bool,
+    // The number of acknowledged payload bytes that were ECN-marked
+    pub(crate) marked_bytes: Bytes,
+    pub(crate) int: IntPath,
+    // The send time of the acknowledged packet, echoed back to the sender
+    pub(crate) sent_at: Time,
+}
+
+// The telemetry of the bottlenecks on a packet's path, in path order
+pub(crate) type IntPath = SmallVec<[IntHop; 1]>;
+
+/// In-band network telemetry stamped by a bottleneck on a packet's path and echoed back to the
+/// sender.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct IntHop {
+    /// The queue length when the packet was dequeued.
+    pub qlen: Bytes,
+    /// The total number of bytes transmitted by the link, including this packet.
+    pub tx_bytes: Bytes,
+    /// The time the packet was dequeued.
+    pub ts: Time,
+    /// The link capacity when the packet was dequeued, which is zero during an outage.
+    pub bandwidth: BitsPerSec,
 }
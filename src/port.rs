@@ -1,79 +1,103 @@
+mod scheduler;
+
 use std::{
-    collections::VecDeque,
+    cmp,
+    collections::{hash_map::Entry, BTreeSet, VecDeque},
     ops::{Index, IndexMut},
 };
 
-use crate::{packet::Packet, units::Bytes};
+use rustc_hash::FxHashMap;
 
-#[derive(Debug, Clone)]
+use crate::{data::QueueStats, packet::Packet, time::Time, units::Bytes, FlowId};
+
+use self::scheduler::Scheduler;
+pub use self::scheduler::SchedulerKind;
+
+#[derive(Debug)]
 pub(crate) struct Port {
     queues: Vec<Queue>,
-    quanta: Vec<Bytes>,
-    deficits: Vec<Bytes>,
-    counter: usize,
-    should_bump: bool,
+    scheduler: Box<dyn Scheduler>,
+
+    // Buffer management and statistics
+    shared_buffer: Option<SharedBuffer>,
+    last_update: Time,
 }
 
 impl Port {
-    pub(crate) fn new(quanta: &[Bytes]) -> Self {
-        let nr_queues = quanta.len();
+    // PRECONDITION: All quanta must be nonzero
+    pub(crate) fn new(quanta: &[Bytes], scheduler: &SchedulerKind) -> Self {
         Self {
-            queues: (0..nr_queues).map(|_| Queue::default()).collect(),
-            quanta: Vec::from(quanta),
-            deficits: vec![Bytes::ZERO; nr_queues],
-            counter: 0,
-            should_bump: true,
+            queues: (0..quanta.len()).map(|_| Queue::default()).collect(),
+            scheduler: scheduler.build(quanta),
+            shared_buffer: None,
+            last_update: Time::ZERO,
         }
     }
 
-    // PRECONDITION: All quanta must be nonzero
-    // This routine returns `None` iff all queues are empty. Otherwise, queue indices are returned
-    // in deficit round-robin order according to the configured quanta.
-    #[must_use]
-    pub(crate) fn pick_dequeue_index(&mut self) -> Option<QIndex> {
-        let n = self.queues.len();
-        let start = self.counter;
-        loop {
-            if self.counter - start == n {
-                // All queues are empty
-                return None;
-            }
-            let idx = self.counter % n;
-            if self.queues[idx].is_empty() {
-                self.deficits[idx] = Bytes::ZERO;
-                self.counter += 1;
-                self.should_bump = true;
-            } else {
-                break;
-            }
+    // PRECONDITION: There must be one alpha per queue
+    pub(crate) fn with_shared_buffer(mut self, shared_buffer: SharedBuffer) -> Self {
+        assert_eq!(shared_buffer.alphas.len(), self.queues.len());
+        self.shared_buffer = Some(shared_buffer);
+        self
+    }
+
+    // Makes every queue serve packets by priority.
+    pub(crate) fn with_priority_queues(mut self) -> Self {
+        for q in &mut self.queues {
+            *q = Queue::by_priority();
         }
-        // The previous step guarantees that there exists a nonempty queue at this point. Since we
-        // assume all quanta are nonzero and positive, we are guaranteed to find some queue that
-        // has accumulated enough deficit to send.
-        loop {
-            let idx = self.counter % n;
-            if self.queues[idx].is_empty() {
-                self.deficits[idx] = Bytes::ZERO;
-                self.counter += 1;
-                self.should_bump = true;
-                continue;
-            }
-            // `self.queues[idx]` is now guaranteed to be nonempty
-            if self.should_bump {
-                self.deficits[idx] += self.quanta[idx];
-                self.should_bump = false;
-            }
-            let cost = self.queues[idx].peek().unwrap().size;
-            if self.deficits[idx] >= cost {
-                self.deficits[idx] -= cost;
-                break Some(QIndex::new(idx));
-            } else {
-                self.counter += 1;
-                self.should_bump = true;
-                continue;
+        self
+    }
+
+    // Returns whether a packet of size `size` may be enqueued at `qindex`. Without a shared
+    // buffer, every packet is admitted. Otherwise, a queue is admitted packets only while its
+    // length is below its dynamic threshold, alpha times the free buffer space (Choudhury and
+    // Hahne), and the packet fits in the free buffer space.
+    pub(crate) fn admits(&self, qindex: QIndex, size: Bytes) -> bool {
+        let Some(buffer) = &self.shared_buffer else {
+            return true;
+        };
+        let free = buffer.size.saturating_sub(self.size());
+        let threshold = free.scale_by(buffer.alphas[qindex.inner()]);
+        size <= free && self[qindex].size() < threshold
+    }
+
+    // Accumulates queue occupancy up to `now`. This must be called before any queue changes size.
+    pub(crate) fn advance(&mut self, now: Time) {
+        let elapsed = (now - self.last_update).into_u128();
+        if elapsed > 0 {
+            for q in &mut self.queues {
+                q.occupancy += u128::from(q.qsize.into_u64()) * elapsed;
             }
+            self.last_update = now;
         }
     }
+
+    pub(crate) fn stats(&self) -> Vec<QueueStats> {
+        let elapsed = self.last_update.into_u128();
+        self.queues
+            .iter()
+            .enumerate()
+            .map(|(i, q)| QueueStats {
+                qindex: QIndex::new(i),
+                max_occupancy: q.max_qsize,
+                mean_occupancy: Bytes::new(q.occupancy.checked_div(elapsed).unwrap_or(0) as u64),
+                drops: q.drops,
+            })
+            .collect()
+    }
+
+    // The total number of bytes queued across all queues.
+    pub(crate) fn size(&self) -> Bytes {
+        self.queues.iter().map(Queue::size).sum()
+    }
+
+    // This routine returns `None` iff all queues are empty. Otherwise, queue indices are returned
+    // in the order chosen by the port's scheduler.
+    #[must_use]
+    pub(crate) fn pick_dequeue_index(&mut self) -> Option<QIndex> {
+        self.scheduler.pick(&self.queues)
+    }
 }
 
 /// A queue index for a port.
@@ -125,25 +149,84 @@ impl IndexMut<QIndex> for Port {
     }
 }
 
-#[derive(Debug, Default, Clone, derive_new::new)]
+/// A shared switch buffer managed with dynamic thresholds.
+///
+/// Each queue may only grow while its length is below its alpha times the unused buffer space,
+/// so queues share the buffer fairly while leaving headroom for queues that become active later.
+/// Built with [`SharedBuffer::builder`].
+#[derive(Debug, Clone, typed_builder::TypedBuilder, serde::Serialize, serde::Deserialize)]
+#[non_exhaustive]
+pub struct SharedBuffer {
+    /// The total buffer size.
+    #[builder(setter(into))]
+    pub size: Bytes,
+    /// The dynamic threshold parameter of each queue.
+    pub alphas: Vec<f64>,
+}
+
+#[derive(Debug, Default, Clone)]
 pub(crate) struct Queue {
-    inner: VecDeque<Packet>,
+    inner: Packets,
     qsize: Bytes,
+
+    // Statistics
+    max_qsize: Bytes,
+    // Time-integrated queue size, in byte-nanoseconds
+    occupancy: u128,
+    pub(crate) drops: usize,
 }
 
 impl Queue {
+    // Creates a queue that serves packets by priority instead of in FIFO order.
+    pub(crate) fn by_priority() -> Self {
+        Self {
+            inner: Packets::Priority(PriorityIndex::default()),
+            ..Self::default()
+        }
+    }
+
     pub(crate) fn enqueue(&mut self, pkt: Packet) {
         self.qsize += pkt.size;
-        self.inner.push_back(pkt);
+        self.max_qsize = cmp::max(self.max_qsize, self.qsize);
+        match &mut self.inner {
+            Packets::Fifo(packets) => packets.push_back(pkt),
+            Packets::Priority(index) => index.push(pkt),
+        }
     }
 
     pub(crate) fn dequeue(&mut self) -> Option<Packet> {
-        match self.inner.pop_front() {
-            r @ Some(pkt) => {
-                self.qsize -= pkt.size;
-                r
-            }
-            None => None,
+        let pkt = match &mut self.inner {
+            Packets::Fifo(packets) => packets.pop_front(),
+            Packets::Priority(index) => index.head().and_then(|seq| index.remove(seq)),
+        }?;
+        self.qsize -= pkt.size;
+        Some(pkt)
+    }
+
+    // Returns the lowest priority among queued packets if the queue serves packets by priority.
+    pub(crate) fn lowest_priority(&self) -> Option<u64> {
+        match &self.inner {
+            Packets::Fifo(_) => None,
+            Packets::Priority(index) => index.order.last().map(|&(priority, _)| priority),
+        }
+    }
+
+    // Removes the lowest-priority packet, preferring the most recent one among equals. FIFO
+    // queues tail-drop arriving packets instead.
+    pub(crate) fn drop_lowest(&mut self) -> Option<Packet> {
+        let Packets::Priority(index) = &mut self.inner else {
+            return None;
+        };
+        let &(_, seq) = index.order.last()?;
+        let pkt = index.remove(seq)?;
+        self.qsize -= pkt.size;
+        Some(pkt)
+    }
+
+    pub(crate) fn peek(&self) -> Option<&Packet> {
+        match &self.inner {
+            Packets::Fifo(packets) => packets.front(),
+            Packets::Priority(index) => index.head().and_then(|seq| index.packets.get(&seq)),
         }
     }
 
@@ -151,12 +234,66 @@ impl Queue {
         self.qsize
     }
 
-    delegate::delegate! {
-        to self.inner {
-            #[call(front)]
-            pub(crate) fn peek(&self) -> Option<&Packet>;
-            pub(crate) fn is_empty(&self) -> bool;
+    pub(crate) fn is_empty(&self) -> bool {
+        match &self.inner {
+            Packets::Fifo(packets) => packets.is_empty(),
+            Packets::Priority(index) => index.packets.is_empty(),
+        }
+    }
+}
+
+// The packets of a queue, either in FIFO order or indexed by priority
+#[derive(Debug, Clone)]
+enum Packets {
+    Fifo(VecDeque<Packet>),
+    Priority(PriorityIndex),
+}
+
+impl Default for Packets {
+    fn default() -> Self {
+        Packets::Fifo(VecDeque::new())
+    }
+}
+
+// Packets indexed by priority and by flow, so that finding the next packet to serve or drop takes
+// logarithmic time. Packets are identified by their arrival sequence numbers.
+#[derive(Debug, Default, Clone)]
+struct PriorityIndex {
+    packets: FxHashMap<u64, Packet>,
+    // Sequence numbers in priority order, ties broken by arrival order
+    order: BTreeSet<(u64, u64)>,
+    // The sequence numbers of each flow's packets, in arrival order
+    flows: FxHashMap<FlowId, BTreeSet<u64>>,
+    next_seq: u64,
+}
+
+impl PriorityIndex {
+    fn push(&mut self, pkt: Packet) {
+        let seq = self.next_seq;
+        self.next_seq += 1;
+        self.order.insert((pkt.priority, seq));
+        self.flows.entry(pkt.flow_id).or_default().insert(seq);
+        self.packets.insert(seq, pkt);
+    }
+
+    // The next packet is the earliest packet of the flow that owns the highest-priority packet,
+    // as in pFabric, so that flows are never reordered.
+    fn head(&self) -> Option<u64> {
+        let &(_, best) = self.order.first()?;
+        let flow = self.packets[&best].flow_id;
+        self.flows[&flow].first().copied()
+    }
+
+    fn remove(&mut self, seq: u64) -> Option<Packet> {
+        let pkt = self.packets.remove(&seq)?;
+        self.order.remove(&(pkt.priority, seq));
+        if let Entry::Occupied(mut e) = self.flows.entry(pkt.flow_id) {
+            e.get_mut().remove(&seq);
+            if e.get().is_empty() {
+                e.remove();
+            }
         }
+        Some(pkt)
     }
 }
 
@@ -184,16 +321,26 @@ mod tests {
         }
     }
 
+    // Drains the port and returns the indices of the queues it served, in order
+    fn dequeue_all(port: &mut Port) -> Vec<usize> {
+        let mut sequence = Vec::new();
+        while let Some(qidx) = port.pick_dequeue_index() {
+            port[qidx].dequeue().expect("unexpected empty queue");
+            sequence.push(qidx.inner());
+        }
+        sequence
+    }
+
     #[test]
     fn drr_empty_none() -> anyhow::Result<()> {
-        let mut port = Port::new(&[Bytes::new(1); 8]);
+        let mut port = Port::new(&[Bytes::new(1); 8], &SchedulerKind::Drr);
         assert!(port.pick_dequeue_index().is_none());
         Ok(())
     }
 
     #[test]
     fn drr_nonempty_some() -> anyhow::Result<()> {
-        let mut port = Port::new(&[Bytes::new(1); 8]);
+        let mut port = Port::new(&[Bytes::new(1); 8], &SchedulerKind::Drr);
         let pkt = mk_pkt(FlowId::ZERO, QIndex::ZERO, Bytes::new(1_000));
         port[pkt.qindex].enqueue(pkt);
         assert_eq!(port.pick_dequeue_index(), Some(QIndex::ZERO));
@@ -202,7 +349,7 @@ mod tests {
 
     #[test]
     fn drr_empty_resets_deficit() -> anyhow::Result<()> {
-        let mut port = Port::new(&[Bytes::new(1); 2]);
+        let mut port = Port::new(&[Bytes::new(1); 2], &SchedulerKind::Drr);
 
         // One packet in queue 0
         let pkt = mk_pkt(FlowId::ZERO, QIndex::ZERO, Bytes::new(1_000));
@@ -232,15 +379,144 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn strict_priority_serves_lower_indices_first() -> anyhow::Result<()> {
+        let mut port = Port::new(&[Bytes::new(1); 2], &SchedulerKind::StrictPriority);
+        for _ in 0..3 {
+            port[QIndex::ONE].enqueue(mk_pkt(FlowId::ONE, QIndex::ONE, Bytes::new(1_000)));
+        }
+        for _ in 0..2 {
+            port[QIndex::ZERO].enqueue(mk_pkt(FlowId::ZERO, QIndex::ZERO, Bytes::new(1_000)));
+        }
+        assert_eq!(dequeue_all(&mut port), [0, 0, 1, 1, 1]);
+        Ok(())
+    }
+
+    #[test]
+    fn wfq_respects_weights() -> anyhow::Result<()> {
+        let mut port = Port::new(&[Bytes::new(1), Bytes::new(4)], &SchedulerKind::Wfq);
+
+        let pkt1 = mk_pkt(FlowId::ZERO, QIndex::ZERO, Bytes::ONE);
+        let pkt2 = mk_pkt(FlowId::ONE, QIndex::ONE, Bytes::ONE);
+        for _ in 0..5 {
+            port[pkt1.qindex].enqueue(pkt1.clone());
+            port[pkt2.qindex].enqueue(pkt2.clone());
+        }
+
+        // Ties in virtual finish time go to the lower queue index
+        assert_eq!(dequeue_all(&mut port), [1, 1, 1, 0, 1, 1, 0, 0, 0, 0]);
+        Ok(())
+    }
+
+    #[test]
+    fn sp_drr_round_robins_within_a_level() -> anyhow::Result<()> {
+        let scheduler = SchedulerKind::SpDrr {
+            levels: vec![0, 1, 1],
+        };
+        let mut port = Port::new(&[Bytes::new(1_000); 3], &scheduler);
+        for idx in [2, 1, 0] {
+            for _ in 0..2 {
+                let qindex = QIndex::new(idx);
+                port[qindex].enqueue(mk_pkt(FlowId::new(idx), qindex, Bytes::new(1_000)));
+            }
+        }
+        assert_eq!(dequeue_all(&mut port), [0, 0, 1, 2, 1, 2]);
+        Ok(())
+    }
+
+    #[test]
+    fn priority_queue_serves_flows_in_order() -> anyhow::Result<()> {
+        let mut port = Port::new(&[Bytes::new(1)], &SchedulerKind::Drr).with_priority_queues();
+        let mk = |flow_id, priority| Packet {
+            priority,
+            ..mk_pkt(FlowId::new(flow_id), QIndex::ZERO, Bytes::new(1_000))
+        };
+        for pkt in [mk(0, 5), mk(1, 3), mk(0, 4), mk(1, 2), mk(2, 4)] {
+            port[QIndex::ZERO].enqueue(pkt);
+        }
+        // The lowest-priority packet is dropped first, and the most recent one among equals
+        assert_eq!(port[QIndex::ZERO].lowest_priority(), Some(5));
+        assert_eq!(port[QIndex::ZERO].drop_lowest().unwrap().priority, 5);
+        assert_eq!(
+            port[QIndex::ZERO].drop_lowest().unwrap().flow_id,
+            FlowId::new(2)
+        );
+        // Flow 1 holds the highest-priority packet, but its packets leave in order
+        let mut sequence = Vec::new();
+        while let Some(pkt) = port[QIndex::ZERO].dequeue() {
+            sequence.push((pkt.flow_id.into_usize(), pkt.priority));
+        }
+        assert_eq!(sequence, vec![(1, 3), (1, 2), (0, 4)]);
+        assert!(port[QIndex::ZERO].is_empty());
+        assert_eq!(port[QIndex::ZERO].size(), Bytes::ZERO);
+        Ok(())
+    }
+
+    #[test]
+    fn fifo_queue_ignores_priorities() -> anyhow::Result<()> {
+        let mut queue = Queue::default();
+        for (flow_id, priority) in [(0, 5), (1, 3)] {
+            queue.enqueue(Packet {
+                priority,
+                ..mk_pkt(FlowId::new(flow_id), QIndex::ZERO, Bytes::new(1_000))
+            });
+        }
+        assert_eq!(queue.lowest_priority(), None);
+        assert!(queue.drop_lowest().is_none());
+        assert_eq!(queue.dequeue().unwrap().flow_id, FlowId::ZERO);
+        Ok(())
+    }
+
+    #[test]
+    fn shared_buffer_dynamic_threshold() -> anyhow::Result<()> {
+        let buffer = SharedBuffer::builder()
+            .size(Bytes::new(10_000))
+            .alphas(vec![1.0, 0.25])
+            .build();
+        let mut port =
+            Port::new(&[Bytes::new(1); 2], &SchedulerKind::Drr).with_shared_buffer(buffer);
+        let sz = Bytes::new(1_000);
+
+        // With alpha = 1, queue 0 grows until it holds half of the buffer
+        let mut nr_admitted = 0;
+        while port.admits(QIndex::ZERO, sz) {
+            port[QIndex::ZERO].enqueue(mk_pkt(FlowId::ZERO, QIndex::ZERO, sz));
+            nr_admitted += 1;
+        }
+        assert_eq!(nr_admitted, 5);
+
+        // Queue 1 may only take a quarter of the remaining space
+        assert!(port.admits(QIndex::ONE, sz));
+        port[QIndex::ONE].enqueue(mk_pkt(FlowId::ONE, QIndex::ONE, sz));
+        assert!(!port.admits(QIndex::ONE, sz));
+        Ok(())
+    }
+
+    #[test]
+    fn queue_stats_track_occupancy() -> anyhow::Result<()> {
+        let mut port = Port::new(&[Bytes::new(1)], &SchedulerKind::Drr);
+        let sz = Bytes::new(1_000);
+        port[QIndex::ZERO].enqueue(mk_pkt(FlowId::ZERO, QIndex::ZERO, sz));
+        port[QIndex::ZERO].enqueue(mk_pkt(FlowId::ZERO, QIndex::ZERO, sz));
+        port.advance(Time::new(10));
+        port[QIndex::ZERO].dequeue();
+        port[QIndex::ZERO].dequeue();
+        port.advance(Time::new(20));
+        let stats = port.stats();
+        assert_eq!(stats[0].max_occupancy, Bytes::new(2_000));
+        assert_eq!(stats[0].mean_occupancy, Bytes::new(1_000));
+        Ok(())
+    }
+
     #[test]
     fn drr_respects_weights() -> anyhow::Result<()> {
-        let mut port = Port::new(&[Bytes::new(1), Bytes::new(3)]);
+        let mut port = Port::new(&[Bytes::new(1), Bytes::new(3)], &SchedulerKind::Drr);
 
         let pkt1 = mk_pkt(FlowId::ZERO, QIndex::ZERO, Bytes::ONE);
         let pkt2 = mk_pkt(FlowId::ONE, QIndex::ONE, Bytes::ONE);
         for _ in 0..6 {
-            port[pkt1.qindex].enqueue(pkt1);
-            port[pkt2.qindex].enqueue(pkt2);
+            port[pkt1.qindex].enqueue(pkt1.clone());
+            port[pkt2.qindex].enqueue(pkt2.clone());
         }
 
         check_drr_sequence(&mut port, &[0, 1, 1, 1, 0, 1, 1, 1, 0, 0, 0, 0]);
@@ -0,0 +1,207 @@
+use std::fmt;
+
+use crate::units::Bytes;
+
+use super::{QIndex, Queue};
+
+/// A packet scheduler decides which queue of a port transmits next.
+pub(crate) trait Scheduler: fmt::Debug {
+    // Returns `None` iff all queues are empty. The caller must dequeue exactly one packet from the
+    // returned queue before calling this routine again.
+    fn pick(&mut self, queues: &[Queue]) -> Option<QIndex>;
+}
+
+/// A packet scheduling policy.
+#[derive(Debug, Default, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum SchedulerKind {
+    /// Deficit round robin, using the switch quanta as weights.
+    #[default]
+    Drr,
+    /// Strict priority. Lower queue indices are served first.
+    StrictPriority,
+    /// Weighted fair queueing (self-clocked virtual time), using the switch quanta as weights.
+    Wfq,
+    /// Strict priority across priority levels and deficit round robin among the queues of a
+    /// level. Queue `i` has level `levels[i]`, and lower levels are served first.
+    SpDrr {
+        /// The priority level of each queue.
+        levels: Vec<usize>,
+    },
+}
+
+impl SchedulerKind {
+    // PRECONDITION: All quanta must be nonzero
+    pub(crate) fn build(&self, quanta: &[Bytes]) -> Box<dyn Scheduler> {
+        match self {
+            SchedulerKind::Drr => Box::new(Drr::new(quanta, (0..quanta.len()).collect())),
+            SchedulerKind::StrictPriority => Box::new(StrictPriority),
+            SchedulerKind::Wfq => Box::new(Wfq::new(quanta)),
+            SchedulerKind::SpDrr { levels } => Box::new(SpDrr::new(quanta, levels)),
+        }
+    }
+}
+
+// Deficit round robin over a subset of a port's queues.
+#[derive(Debug, Clone)]
+struct Drr {
+    members: Vec<usize>,
+    quanta: Vec<Bytes>,
+    deficits: Vec<Bytes>,
+    counter: usize,
+    should_bump: bool,
+}
+
+impl Drr {
+    fn new(quanta: &[Bytes], members: Vec<usize>) -> Self {
+        Self {
+            quanta: members.iter().map(|&i| quanta[i]).collect(),
+            deficits: vec![Bytes::ZERO; members.len()],
+            members,
+            counter: 0,
+            should_bump: true,
+        }
+    }
+
+    fn has_packets(&self, queues: &[Queue]) -> bool {
+        self.members.iter().any(|&i| !queues[i].is_empty())
+    }
+}
+
+impl Scheduler for Drr {
+    // Queue indices are returned in deficit round-robin order according to the configured quanta.
+    fn pick(&mut self, queues: &[Queue]) -> Option<QIndex> {
+        let n = self.members.len();
+        let start = self.counter;
+        loop {
+            if self.counter - start == n {
+                // All queues are empty
+                return None;
+            }
+            let idx = self.counter % n;
+            if queues[self.members[idx]].is_empty() {
+                self.deficits[idx] = Bytes::ZERO;
+                self.counter += 1;
+                self.should_bump = true;
+            } else {
+                break;
+            }
+        }
+        // The previous step guarantees that there exists a nonempty queue at this point. Since we
+        // assume all quanta are nonzero and positive, we are guaranteed to find some queue that
+        // has accumulated enough deficit to send.
+        loop {
+            let idx = self.counter % n;
+            let queue = &queues[self.members[idx]];
+            if queue.is_empty() {
+                self.deficits[idx] = Bytes::ZERO;
+                self.counter += 1;
+                self.should_bump = true;
+                continue;
+            }
+            // `queue` is now guaranteed to be nonempty
+            if self.should_bump {
+                self.deficits[idx] += self.quanta[idx];
+                self.should_bump = false;
+            }
+            let cost = queue.peek().unwrap().size;
+            if self.deficits[idx] >= cost {
+                self.deficits[idx] -= cost;
+                break Some(QIndex::new(self.members[idx]));
+            } else {
+                self.counter += 1;
+                self.should_bump = true;
+                continue;
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct StrictPriority;
+
+impl Scheduler for StrictPriority {
+    fn pick(&mut self, queues: &[Queue]) -> Option<QIndex> {
+        queues.iter().position(|q| !q.is_empty()).map(QIndex::new)
+    }
+}
+
+// Self-clocked fair queueing. Each head-of-line packet is stamped with a virtual finish time, and
+// the packet with the smallest one is served next. The virtual time is the finish time of the
+// packet most recently picked.
+#[derive(Debug, Clone)]
+struct Wfq {
+    weights: Vec<f64>,
+    vtime: f64,
+    last_finish: Vec<f64>,
+    // The finish time of each queue's head-of-line packet, once stamped
+    head_finish: Vec<Option<f64>>,
+}
+
+impl Wfq {
+    fn new(quanta: &[Bytes]) -> Self {
+        Self {
+            weights: quanta.iter().map(|q| q.into_f64()).collect(),
+            vtime: 0.0,
+            last_finish: vec![0.0; quanta.len()],
+            head_finish: vec![None; quanta.len()],
+        }
+    }
+}
+
+impl Scheduler for Wfq {
+    fn pick(&mut self, queues: &[Queue]) -> Option<QIndex> {
+        let mut best: Option<(usize, f64)> = None;
+        for (i, queue) in queues.iter().enumerate() {
+            let Some(pkt) = queue.peek() else {
+                continue;
+            };
+            let finish = *self.head_finish[i].get_or_insert_with(|| {
+                self.vtime.max(self.last_finish[i]) + pkt.size.into_f64() / self.weights[i]
+            });
+            if best.is_none_or(|(_, f)| finish < f) {
+                best = Some((i, finish));
+            }
+        }
+        let Some((i, finish)) = best else {
+            // The system is idle, so the virtual time restarts
+            self.vtime = 0.0;
+            self.last_finish.fill(0.0);
+            return None;
+        };
+        self.vtime = finish;
+        self.last_finish[i] = finish;
+        self.head_finish[i] = None;
+        Some(QIndex::new(i))
+    }
+}
+
+#[derive(Debug, Clone)]
+struct SpDrr {
+    // DRR schedulers in decreasing order of priority
+    levels: Vec<Drr>,
+}
+
+impl SpDrr {
+    fn new(quanta: &[Bytes], levels: &[usize]) -> Self {
+        let mut distinct = levels.to_vec();
+        distinct.sort_unstable();
+        distinct.dedup();
+        let levels = distinct
+            .into_iter()
+            .map(|level| {
+                let members = (0..levels.len()).filter(|&i| levels[i] == level).collect();
+                Drr::new(quanta, members)
+            })
+            .collect();
+        Self { levels }
+    }
+}
+
+impl Scheduler for SpDrr {
+    fn pick(&mut self, queues: &[Queue]) -> Option<QIndex> {
+        self.levels
+            .iter_mut()
+            .find(|drr| drr.has_packets(queues))
+            .and_then(|drr| drr.pick(queues))
+    }
+}
@@ -0,0 +1,331 @@
+use std::{cmp, collections::BTreeMap};
+
+use rustc_hash::FxHashMap;
+use smallvec::SmallVec;
+
+use crate::{
+    flow::Retransmission,
+    packet::Ack,
+    time::Time,
+    units::{Bytes, Microsecs, Nanosecs},
+    FlowId, Packet,
+};
+
+// The receiving end of every flow. Since packets reach their destination in the order in which
+// they leave the bottleneck, the receiver runs as soon as a packet is dequeued.
+#[derive(Debug, Default)]
+pub(crate) struct Receiver {
+    flows: FxHashMap<FlowId, RcvState>,
+    acks: FxHashMap<FlowId, AckState>,
+    // The number of ACK timers armed so far, used to tell them apart
+    nr_timers: u64,
+}
+
+// The delayed ACK state of a flow
+#[derive(Debug, Default)]
+struct AckState {
+    // The CE state of the DCTCP ECN-echo state machine
+    ce: bool,
+    pending: Option<PendingAck>,
+    // Identifies the timer armed for the current pending ACK
+    version: u64,
+}
+
+#[derive(Debug)]
+struct PendingAck {
+    // The latest packet covered by the ACK and the ACK it would have generated on its own
+    pkt: Packet,
+    ack: Ack,
+    arrived_at: Time,
+    nr_packets: usize,
+    marked_bytes: Bytes,
+}
+
+impl PendingAck {
+    fn into_ack(self) -> (Packet, Ack) {
+        let ack = Ack {
+            marked_bytes: self.marked_bytes,
+            ..self.ack
+        };
+        (self.pkt, ack)
+    }
+}
+
+// The result of coalescing one packet's ACK
+#[derive(Debug)]
+pub(crate) struct Coalesced {
+    // The ACKs to send right away, each with the latest packet it covers
+    pub(crate) acks: SmallVec<[(Packet, Ack); 2]>,
+    // The version of an ACK timer to arm, if any
+    pub(crate) timer: Option<u64>,
+}
+
+#[derive(Debug, Default)]
+struct RcvState {
+    rcv_nxt: Bytes,
+    end: Option<Bytes>,
+    // Out-of-order data buffered for selective retransmission
+    ooo: BTreeMap<Bytes, Bytes>,
+    nacked: bool,
+    done: bool,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Delivery {
+    // The cumulative ACK number
+    pub(crate) ackno: Bytes,
+    pub(crate) sack: Option<(Bytes, Bytes)>,
+    pub(crate) nack: bool,
+    // Whether this packet completed the flow
+    pub(crate) is_complete: bool,
+}
+
+impl Receiver {
+    // Without loss recovery, packets can never be lost or reordered, so no state is kept.
+    pub(crate) fn receive(
+        &mut self,
+        pkt: &Packet,
+        payload: Bytes,
+        mode: Option<Retransmission>,
+    ) -> Delivery {
+        let (start, end) = (pkt.seq, pkt.seq + payload);
+        let Some(mode) = mode else {
+            return Delivery {
+                ackno: end,
+                sack: None,
+                nack: false,
+                is_complete: pkt.is_last,
+            };
+        };
+        let st = self.flows.entry(pkt.flow_id).or_default();
+        if pkt.is_last {
+            st.end = Some(end);
+        }
+        let (mut sack, mut nack) = (None, false);
+        if start <= st.rcv_nxt {
+            if end > st.rcv_nxt {
+                st.rcv_nxt = end;
+                st.nacked = false;
+            }
+        } else {
+            match mode {
+                Retransmission::GoBackN => {
+                    // Out-of-order data is discarded, and the sender is told to go back once
+                    if !st.nacked {
+                        nack = true;
+                        st.nacked = true;
+                    }
+                }
+                Retransmission::Selective => {
+                    merge_range(&mut st.ooo, start, end);
+                    sack = Some((start, end));
+                }
+            }
+        }
+        while let Some((&s, &e)) = st.ooo.first_key_value() {
+            if s > st.rcv_nxt {
+                break;
+            }
+            st.ooo.pop_first();
+            st.rcv_nxt = cmp::max(st.rcv_nxt, e);
+        }
+        let is_complete = !st.done && st.end == Some(st.rcv_nxt);
+        st.done |= is_complete;
+        Delivery {
+            ackno: st.rcv_nxt,
+            sack,
+            nack,
+            is_complete,
+        }
+    }
+}
+
+impl Receiver {
+    // Holds back the ACK for `pkt` until `cfg.nr_packets` packets have arrived, unless it has to
+    // be sent immediately. As in DCTCP, a change in the packets' CE marks also causes the pending
+    // ACK to be sent at once, so that every ACK echoes the marks of the packets it covers.
+    pub(crate) fn coalesce(
+        &mut self,
+        pkt: &Packet,
+        ack: Ack,
+        arrived_at: Time,
+        immediate: bool,
+        cfg: DelayedAck,
+    ) -> Coalesced {
+        let st = self.acks.entry(pkt.flow_id).or_default();
+        let mut acks = SmallVec::new();
+        let ce = ack.marked_bytes > Bytes::ZERO;
+        if ce != st.ce {
+            acks.extend(st.pending.take().map(PendingAck::into_ack));
+            st.ce = ce;
+        }
+        let is_new = st.pending.is_none();
+        let (nr_packets, marked_bytes) = st
+            .pending
+            .as_ref()
+            .map_or((0, Bytes::ZERO), |p| (p.nr_packets, p.marked_bytes));
+        let pending = st.pending.insert(PendingAck {
+            pkt: pkt.clone(),
+            marked_bytes: marked_bytes + ack.marked_bytes,
+            ack,
+            arrived_at,
+            nr_packets: nr_packets + 1,
+        });
+        let mut timer = None;
+        if immediate || pending.nr_packets >= cfg.nr_packets {
+            acks.extend(st.pending.take().map(PendingAck::into_ack));
+        } else if is_new {
+            self.nr_timers += 1;
+            st.version = self.nr_timers;
+            timer = Some(st.version);
+        }
+        if pkt.is_last && immediate {
+            // The flow is done, unless it retransmits
+            self.acks.remove(&pkt.flow_id);
+        }
+        Coalesced { acks, timer }
+    }
+
+    // Returns the pending ACK of the flow if the timer with version `version` is still current.
+    // The echoed send time is shifted by the time the ACK was held back, so that the sender's RTT
+    // samples exclude it.
+    pub(crate) fn ack_timeout(
+        &mut self,
+        flow_id: FlowId,
+        version: u64,
+        now: Time,
+    ) -> Option<(Packet, Ack)> {
+        let st = self.acks.get_mut(&flow_id)?;
+        if st.version != version {
+            return None;
+        }
+        let pending = st.pending.take()?;
+        let held = now.saturating_sub(pending.arrived_at);
+        let (pkt, ack) = pending.into_ack();
+        let ack = Ack {
+            sent_at: ack.sent_at + held,
+            ..ack
+        };
+        Some((pkt, ack))
+    }
+}
+
+/// Delayed ACK parameters.
+///
+/// The receiver sends one cumulative ACK for every `nr_packets` in-order packets, or when
+/// `timeout` expires after the first unacknowledged packet arrived. Out-of-order packets,
+/// retransmissions, and the last packet of a flow are acknowledged immediately. The time an ACK
+/// is held back is excluded from the sender's RTT samples.
+#[derive(Debug, Clone, Copy, typed_builder::TypedBuilder, serde::Serialize, serde::Deserialize)]
+pub struct DelayedAck {
+    /// The number of packets covered by each ACK.
+    #[builder(default = 2)]
+    pub nr_packets: usize,
+    /// The maximum time an ACK is held back. The default is Linux's minimum delayed ACK timeout
+    /// scaled down by a factor of 1000.
+    #[builder(default = Microsecs::new(40).into_ns(), setter(into))]
+    pub timeout: Nanosecs,
+}
+
+impl Default for DelayedAck {
+    fn default() -> Self {
+        Self::builder().build()
+    }
+}
+
+// Inserts `[start, end)` into a set of disjoint byte ranges, merging any overlapping or adjacent
+// ranges.
+pub(crate) fn merge_range(ranges: &mut BTreeMap<Bytes, Bytes>, start: Bytes, end: Bytes) {
+    let (mut start, mut end) = (start, end);
+    let overlapping = ranges
+        .range(..=end)
+        .filter(|(_, &e)| e >= start)
+        .map(|(&s, _)| s)
+        .collect::<Vec<_>>();
+    for s in overlapping {
+        let e = ranges.remove(&s).unwrap();
+        start = cmp::min(start, s);
+        end = cmp::max(end, e);
+    }
+    ranges.insert(start, end);
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{port::QIndex, LinkId, SourceId};
+
+    use super::*;
+
+    const PAYLOAD: Bytes = Bytes::new(1_000);
+
+    fn rcv(receiver: &mut Receiver, nr: u64, marked: bool) -> Coalesced {
+        let pkt = Packet::builder()
+            .flow_id(FlowId::ZERO)
+            .source_id(SourceId::ZERO)
+            .qindex(QIndex::ZERO)
+            .link(LinkId::ZERO)
+            .seq(PAYLOAD.scale_by(nr as f64))
+            .size(PAYLOAD)
+            .src2btl(Nanosecs::ZERO)
+            .btl2dst(Nanosecs::ZERO)
+            .is_last(false)
+            .is_retx(false)
+            .priority(0)
+            .sent_at(Time::ZERO)
+            .build();
+        let marked_bytes = if marked { PAYLOAD } else { Bytes::ZERO };
+        let ack = Ack::new(
+            pkt.seq + PAYLOAD,
+            None,
+            false,
+            marked_bytes,
+            SmallVec::new(),
+            Time::ZERO,
+        );
+        let cfg = DelayedAck::builder().nr_packets(3).build();
+        receiver.coalesce(&pkt, ack, Time::ZERO, false, cfg)
+    }
+
+    fn acked(coalesced: &Coalesced) -> Vec<(u64, u64)> {
+        coalesced
+            .acks
+            .iter()
+            .map(|(_, ack)| (ack.ackno.into_u64(), ack.marked_bytes.into_u64()))
+            .collect()
+    }
+
+    #[test]
+    fn acks_are_coalesced() {
+        let mut receiver = Receiver::default();
+        let first = rcv(&mut receiver, 0, false);
+        assert!(first.acks.is_empty());
+        let version = first.timer.unwrap();
+        assert!(acked(&rcv(&mut receiver, 1, false)).is_empty());
+        assert_eq!(acked(&rcv(&mut receiver, 2, false)), vec![(3_000, 0)]);
+        // The old timer is stale
+        assert!(rcv(&mut receiver, 3, false).timer.is_some());
+        assert!(receiver
+            .ack_timeout(FlowId::ZERO, version, Time::ZERO)
+            .is_none());
+    }
+
+    #[test]
+    fn ce_changes_flush_pending_acks() {
+        let mut receiver = Receiver::default();
+        rcv(&mut receiver, 0, false);
+        // The pending ACK echoes the old CE state
+        assert_eq!(acked(&rcv(&mut receiver, 1, true)), vec![(1_000, 0)]);
+        assert!(acked(&rcv(&mut receiver, 2, true)).is_empty());
+        assert_eq!(acked(&rcv(&mut receiver, 3, false)), vec![(3_000, 2_000)]);
+        // A timeout sends whatever is pending
+        let version = receiver.acks[&FlowId::ZERO].version;
+        let (_, ack) = receiver
+            .ack_timeout(FlowId::ZERO, version, Time::new(100))
+            .unwrap();
+        assert_eq!(
+            (ack.ackno, ack.marked_bytes),
+            (Bytes::new(4_000), Bytes::ZERO)
+        );
+        assert_eq!(ack.sent_at, Time::new(100));
+    }
+}
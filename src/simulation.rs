@@ -1,15 +1,20 @@
 pub(crate) mod event;
 mod schedule;
 
+use std::rc::Rc;
+
 use rustc_hash::FxHashMap;
 
 use crate::{
-    data::Record,
+    cc::CcParams,
+    data::{BackgroundStats, CoflowRecord, Output, Record},
     entities::{
-        bottleneck::{Bottleneck, BottleneckCmd},
+        background::{Background, BackgroundCmd, BackgroundId},
+        bottleneck::{Bottleneck, BottleneckCmd, LinkId},
         source::{Source, SourceCmd, SourceId},
         workload::{Workload, WorkloadCmd},
     },
+    flow::{CoflowId, LossRecovery, Mlfq},
     time::{Delta, Time},
     units::{BitsPerSec, Bytes},
 };
@@ -30,34 +35,56 @@ pub(crate) struct Simulation {
     // Entities
     workload: Workload,
     sources: FxHashMap<SourceId, Source>,
-    bottleneck: Bottleneck,
+    bottlenecks: FxHashMap<LinkId, Bottleneck>,
+    #[builder(default)]
+    background: FxHashMap<BackgroundId, Background>,
 
     // Rate control configuration
-    #[builder(setter(into))]
-    window: Bytes,
-    dctcp_gain: f64,
-    #[builder(setter(into))]
-    dctcp_ai: BitsPerSec,
+    #[builder(setter(transform = |params: CcParams| Rc::new(params)))]
+    cc_params: Rc<CcParams>,
+
+    // Derived from the bottlenecks
+    #[builder(default, setter(skip))]
+    link_bandwidths: Rc<FxHashMap<LinkId, BitsPerSec>>,
 
     #[builder(setter(into))]
     sz_pktmax: Bytes,
     #[builder(setter(into))]
     sz_pkthdr: Bytes,
+    loss_recovery: Option<LossRecovery>,
+    #[builder(default)]
+    mlfq: Option<Rc<Mlfq>>,
 
     // Used for termination
     timeout: Option<Time>,
+    // The number of flows that have yet to complete. Open-ended background streams stop once it
+    // reaches zero.
+    nr_flows_left: usize,
+    // The number of flows with data in each coflow
+    #[builder(default)]
+    coflows: FxHashMap<CoflowId, usize>,
 }
 
 impl Simulation {
-    pub(crate) fn run(mut self) -> Vec<Record> {
+    pub(crate) fn run(mut self) -> Output {
+        self.link_bandwidths = Rc::new(
+            self.bottlenecks
+                .iter()
+                .map(|(&id, bottleneck)| (id, bottleneck.bandwidth))
+                .collect(),
+        );
         // Kick off the simulation by starting the workload
         let ev = Event::new(Time::ZERO, WorkloadCmd::new_step());
         self.schedule.push(ev);
+        for stream in self.background.values() {
+            let ev = Event::new(stream.start(), BackgroundCmd::new_send(stream.id()));
+            self.schedule.push(ev);
+        }
         // Run the simulation
         while !self.should_stop() {
             self.step();
         }
-        // Return the FCT records
+        // Return the FCT records and statistics
         self.finish()
     }
 
@@ -82,20 +109,94 @@ impl Simulation {
         Context {
             cur_time: self.cur_time,
             events: EventList::new(),
-            btl_bandwidth: self.bottleneck.bandwidth,
-            window: self.window,
-            dctcp_gain: self.dctcp_gain,
-            dctcp_ai: self.dctcp_ai,
+            link_bandwidths: Rc::clone(&self.link_bandwidths),
+            cc_params: Rc::clone(&self.cc_params),
             sz_pktmax: self.sz_pktmax,
             sz_pkthdr: self.sz_pkthdr,
+            loss_recovery: self.loss_recovery,
+            mlfq: self.mlfq.clone(),
         }
     }
 
-    fn finish(self) -> Vec<Record> {
-        self.sources
+    fn finish(self) -> Output {
+        let queues = self.bottlenecks[&LinkId::ZERO].queue_stats();
+        let mut links = self
+            .bottlenecks
+            .values()
+            .map(|bottleneck| bottleneck.stats())
+            .collect::<Vec<_>>();
+        links.sort_by_key(|l| l.id);
+        let mut sources = self
+            .sources
+            .values()
+            .map(|source| source.stats(self.cur_time))
+            .collect::<Vec<_>>();
+        sources.sort_by_key(|s| s.id);
+        let mut background = self
+            .background
+            .values()
+            .map(|stream| {
+                let (delivered, dropped) = self
+                    .bottlenecks
+                    .values()
+                    .filter_map(|bottleneck| bottleneck.background.get(&stream.id()))
+                    .fold((Bytes::ZERO, Bytes::ZERO), |(d, x), &(d2, x2)| {
+                        (d + d2, x + x2)
+                    });
+                BackgroundStats {
+                    delivered,
+                    dropped,
+                    ..stream.stats()
+                }
+            })
+            .collect::<Vec<_>>();
+        background.sort_by_key(|s| s.id);
+        let records = self
+            .sources
+            .into_values()
+            .flat_map(|source| source.records.into_iter())
+            .map(|record| Record {
+                drops: self
+                    .bottlenecks
+                    .values()
+                    .filter_map(|bottleneck| bottleneck.drops.get(&record.id))
+                    .sum(),
+                ..record
+            })
+            .collect::<Vec<_>>();
+        // A coflow completes when all of its flows complete
+        let mut members = FxHashMap::<_, Vec<&Record>>::default();
+        for record in &records {
+            if let Some(id) = record.coflow {
+                members.entry(id).or_default().push(record);
+            }
+        }
+        let mut coflows = members
             .into_iter()
-            .flat_map(|(_, source)| source.records.into_iter())
-            .collect()
+            .filter(|(id, flows)| self.coflows.get(id) == Some(&flows.len()))
+            .map(|(id, flows)| {
+                let start = flows.iter().map(|r| r.start).min().unwrap();
+                let end = flows.iter().map(|r| r.start + r.fct).max().unwrap();
+                let ideal_end = flows.iter().map(|r| r.start + r.ideal).max().unwrap();
+                CoflowRecord {
+                    id,
+                    nr_flows: flows.len(),
+                    size: flows.iter().map(|r| r.size).sum(),
+                    start,
+                    cct: end - start,
+                    ideal: ideal_end - start,
+                }
+            })
+            .collect::<Vec<_>>();
+        coflows.sort_by_key(|c| c.id);
+        Output {
+            records,
+            queues,
+            links,
+            sources,
+            background,
+            coflows,
+        }
     }
 }
 
@@ -106,6 +207,7 @@ impl Simulation {
             Command::Workload(cmd) => self.apply_workload(cmd),
             Command::Source(cmd) => self.apply_source(cmd),
             Command::Bottleneck(cmd) => self.apply_bottleneck(cmd),
+            Command::Background(cmd) => self.apply_background(cmd),
             Command::Test => unreachable!(),
         }
     }
@@ -114,6 +216,7 @@ impl Simulation {
         let ctx = self.context();
         match cmd {
             WorkloadCmd::Step => self.workload.step(ctx),
+            WorkloadCmd::FlowDepart(id) => self.workload.flow_depart(id, ctx),
         }
     }
 
@@ -133,8 +236,24 @@ impl Simulation {
                 source.flow_arrive(desc, ctx)
             }
             SourceCmd::FlowDepart { source, flow } => {
+                self.nr_flows_left -= 1;
+                let source = self.sources.get_mut(&source).expect("invalid source ID");
+                let mut events = source.flow_depart(flow, ctx);
+                // Release any flows waiting on this one
+                events.extend(self.workload.flow_depart(flow, self.context()));
+                events
+            }
+            SourceCmd::FlowTimer { source, flow } => {
+                let source = self.sources.get_mut(&source).expect("invalid source ID");
+                source.flow_timer(flow, ctx)
+            }
+            SourceCmd::Pause { source, qindex } => {
+                let source = self.sources.get_mut(&source).expect("invalid source ID");
+                source.pause(qindex, ctx)
+            }
+            SourceCmd::Resume { source, qindex } => {
                 let source = self.sources.get_mut(&source).expect("invalid source ID");
-                source.flow_depart(flow, ctx)
+                source.resume(qindex, ctx)
             }
         }
     }
@@ -142,8 +261,45 @@ impl Simulation {
     fn apply_bottleneck(&mut self, cmd: BottleneckCmd) -> EventList {
         let ctx = self.context();
         match cmd {
-            BottleneckCmd::Receive(pkt) => self.bottleneck.receive(pkt, ctx),
-            BottleneckCmd::Step => self.bottleneck.step(ctx),
+            BottleneckCmd::Receive(pkt) => {
+                let bottleneck = self
+                    .bottlenecks
+                    .get_mut(&pkt.link)
+                    .expect("invalid link ID");
+                bottleneck.receive(pkt, ctx)
+            }
+            BottleneckCmd::ReceiveAck(pkt, ack) => {
+                let bottleneck = self
+                    .bottlenecks
+                    .get_mut(&pkt.link)
+                    .expect("invalid link ID");
+                bottleneck.receive_ack(pkt, *ack, ctx)
+            }
+            BottleneckCmd::AckTimer {
+                link,
+                flow,
+                version,
+            } => {
+                let bottleneck = self.bottlenecks.get_mut(&link).expect("invalid link ID");
+                bottleneck.ack_timeout(flow, version, ctx)
+            }
+            BottleneckCmd::Step(link) => {
+                let bottleneck = self.bottlenecks.get_mut(&link).expect("invalid link ID");
+                bottleneck.step(ctx)
+            }
+        }
+    }
+
+    fn apply_background(&mut self, cmd: BackgroundCmd) -> EventList {
+        let ctx = self.context();
+        match cmd {
+            BackgroundCmd::Send(id) => {
+                let stream = self.background.get_mut(&id).expect("invalid stream ID");
+                if self.nr_flows_left == 0 && stream.is_open_ended() {
+                    return ctx.into_events();
+                }
+                stream.send(ctx)
+            }
         }
     }
 }
@@ -153,6 +309,7 @@ pub(crate) enum Command {
     Workload(WorkloadCmd),
     Source(SourceCmd),
     Bottleneck(BottleneckCmd),
+    Background(BackgroundCmd),
     Test,
 }
 
@@ -162,12 +319,12 @@ pub(crate) struct Context {
     events: EventList,
 
     // Configuration
-    pub(crate) btl_bandwidth: BitsPerSec,
-    pub(crate) window: Bytes,
-    pub(crate) dctcp_gain: f64,
-    pub(crate) dctcp_ai: BitsPerSec,
+    pub(crate) link_bandwidths: Rc<FxHashMap<LinkId, BitsPerSec>>,
+    pub(crate) cc_params: Rc<CcParams>,
     pub(crate) sz_pktmax: Bytes,
     pub(crate) sz_pkthdr: Bytes,
+    pub(crate) loss_recovery: Option<LossRecovery>,
+    pub(crate) mlfq: Option<Rc<Mlfq>>,
 }
 
 impl Context {
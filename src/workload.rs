@@ -0,0 +1,501 @@
+//! Synthetic workload generation.
+//!
+//! A [`Generator`] draws flow sizes from an [empirical CDF](SizeCdf) and flow arrivals from an
+//! [arrival process](ArrivalProcess) at every source, calibrating the arrival rate so that the
+//! offered load is a given fraction of the bottleneck bandwidth. [`Bursts`] generates structured
+//! many-to-one [patterns](Pattern) such as incast and partition-aggregate.
+
+use std::{f64::consts::PI, path::Path};
+
+use rand::{rngs::StdRng, seq::index, Rng, SeedableRng};
+
+use crate::{
+    port::QIndex,
+    units::{BitsPerSec, Bytes, Nanosecs},
+    DestId, FlowDesc, FlowId, SourceDesc,
+};
+
+/// An empirical flow size distribution.
+///
+/// The CDF is piecewise linear between its points, as in the WebSearch and Hadoop distributions
+/// commonly used in datacenter studies.
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct SizeCdf {
+    // `(size, cumulative probability)` pairs in nondecreasing order, ending at probability 1
+    points: Vec<(Bytes, f64)>,
+}
+
+impl SizeCdf {
+    /// Creates a distribution from `(size, cumulative probability)` pairs. Both sizes and
+    /// probabilities must be nondecreasing. Probabilities are normalized by the last one, so they
+    /// may be given as fractions or percentages.
+    pub fn new(points: Vec<(Bytes, f64)>) -> Result<Self, WorkloadError> {
+        let last = points.last().map_or(0.0, |&(_, p)| p);
+        let is_valid = last > 0.0
+            && points.first().is_some_and(|&(_, p)| p >= 0.0)
+            && points
+                .windows(2)
+                .all(|w| w[0].0 <= w[1].0 && w[0].1 <= w[1].1);
+        if !is_valid {
+            return Err(WorkloadError::InvalidCdf);
+        }
+        let points = points.into_iter().map(|(s, p)| (s, p / last)).collect();
+        Ok(Self { points })
+    }
+
+    /// Parses a distribution with one `size probability` pair per line, with sizes in bytes.
+    /// Empty lines and lines starting with `#` are ignored.
+    pub fn parse(s: &str) -> Result<Self, WorkloadError> {
+        let points = s
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(|line| {
+                let mut fields = line.split_whitespace();
+                let size = fields.next()?.parse::<f64>().ok()?;
+                let p = fields.next()?.parse::<f64>().ok()?;
+                (size >= 0.0).then(|| (Bytes::new(size.round() as u64), p))
+            })
+            .collect::<Option<Vec<_>>>()
+            .ok_or(WorkloadError::InvalidCdf)?;
+        Self::new(points)
+    }
+
+    /// Reads a distribution in the format accepted by [parse](Self::parse) from `path`.
+    pub fn read(path: impl AsRef<Path>) -> Result<Self, WorkloadError> {
+        let s = std::fs::read_to_string(path)?;
+        Self::parse(&s)
+    }
+
+    /// Returns the mean flow size in bytes.
+    pub fn mean(&self) -> f64 {
+        let (first, p0) = self.points[0];
+        let head = first.into_f64() * p0;
+        self.points.windows(2).fold(head, |acc, w| {
+            let ((s0, p0), (s1, p1)) = (w[0], w[1]);
+            acc + (p1 - p0) * (s0.into_f64() + s1.into_f64()) / 2.0
+        })
+    }
+
+    /// Samples a flow size of at least one byte.
+    pub fn sample(&self, rng: &mut impl Rng) -> Bytes {
+        let u = rng.gen::<f64>();
+        let idx = self.points.partition_point(|&(_, p)| p < u);
+        let size = match idx {
+            0 => self.points[0].0.into_f64(),
+            i if i == self.points.len() => self.points[i - 1].0.into_f64(),
+            i => {
+                // Interpolate within the segment
+                let ((s0, p0), (s1, p1)) = (self.points[i - 1], self.points[i]);
+                let frac = if p1 > p0 { (u - p0) / (p1 - p0) } else { 1.0 };
+                s0.into_f64() + frac * (s1.into_f64() - s0.into_f64())
+            }
+        };
+        Bytes::new((size.round() as u64).max(1))
+    }
+}
+
+/// A flow inter-arrival time distribution. The mean is set by the target load.
+#[derive(Debug, Default, Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize)]
+pub enum ArrivalProcess {
+    /// Exponentially distributed inter-arrival times.
+    #[default]
+    Poisson,
+    /// Log-normally distributed inter-arrival times, which are burstier than Poisson arrivals for
+    /// large `sigma`.
+    LogNormal {
+        /// The standard deviation of the underlying normal distribution.
+        sigma: f64,
+    },
+}
+
+impl ArrivalProcess {
+    // Samples an inter-arrival time in nanoseconds with the given mean.
+    fn sample(&self, mean: f64, rng: &mut impl Rng) -> f64 {
+        match *self {
+            ArrivalProcess::Poisson => -(1.0 - rng.gen::<f64>()).ln() * mean,
+            ArrivalProcess::LogNormal { sigma } => {
+                // Choose the location so that the mean is preserved, then apply Box-Muller
+                let mu = mean.ln() - sigma * sigma / 2.0;
+                let (u1, u2) = (1.0 - rng.gen::<f64>(), rng.gen::<f64>());
+                let z = (-2.0 * u1.ln()).sqrt() * (2.0 * PI * u2).cos();
+                (mu + sigma * z).exp()
+            }
+        }
+    }
+}
+
+/// A synthetic workload generator.
+///
+/// Every source draws flows independently from the same size distribution and arrival process.
+/// The aggregate arrival rate is chosen so that the mean offered payload is `load` times
+/// `bandwidth`, which is usually [Config::bandwidth](crate::Config::bandwidth).
+#[derive(Debug, Clone, typed_builder::TypedBuilder)]
+pub struct Generator {
+    /// The sources to generate flows at.
+    pub sources: Vec<SourceDesc>,
+    /// The flow size distribution.
+    pub sizes: SizeCdf,
+    /// The flow inter-arrival time distribution at each source.
+    #[builder(default)]
+    pub arrivals: ArrivalProcess,
+    /// The target load, as a fraction of `bandwidth`.
+    pub load: f64,
+    /// The bandwidth the load is relative to.
+    #[builder(setter(into))]
+    pub bandwidth: BitsPerSec,
+    /// The time after which no more flows arrive.
+    #[builder(setter(into))]
+    pub duration: Nanosecs,
+    /// The propagation delay from the bottleneck to the destination, added to each source's
+    /// delay to the bottleneck.
+    #[builder(setter(into))]
+    pub btl2dst: Nanosecs,
+    /// The queue index of every flow.
+    #[builder(default)]
+    pub qindex: QIndex,
+    /// The random seed.
+    #[builder(default)]
+    pub seed: u64,
+}
+
+impl Generator {
+    /// Generates flows in order of arrival, with IDs assigned in the same order.
+    pub fn generate(&self) -> Result<Vec<FlowDesc>, WorkloadError> {
+        if self.sources.is_empty() {
+            return Err(WorkloadError::NoSources);
+        }
+        if !(self.load > 0.0 && self.load.is_finite()) || self.bandwidth == BitsPerSec::ZERO {
+            return Err(WorkloadError::InvalidLoad);
+        }
+        if let ArrivalProcess::LogNormal { sigma } = self.arrivals {
+            if !(sigma >= 0.0 && sigma.is_finite()) {
+                return Err(WorkloadError::InvalidArrivals);
+            }
+        }
+        // Flows per nanosecond across all sources
+        let rate = self.load * self.bandwidth.into_f64() / 8e9 / self.sizes.mean();
+        let mean_gap = self.sources.len() as f64 / rate;
+        let duration = self.duration.into_f64();
+        let mut rng = StdRng::seed_from_u64(self.seed);
+        let mut flows = Vec::new();
+        for source in &self.sources {
+            let mut t = self.arrivals.sample(mean_gap, &mut rng);
+            while t < duration {
+                let flow = FlowDesc::builder()
+                    .id(FlowId::ZERO)
+                    .source(source.id)
+                    .qindex(self.qindex)
+                    .size(self.sizes.sample(&mut rng))
+                    .start(Nanosecs::new(t.round() as u64))
+                    .delay2dst(source.delay2btl + self.btl2dst)
+                    .build();
+                flows.push(flow);
+                t += self.arrivals.sample(mean_gap, &mut rng);
+            }
+        }
+        flows.sort_by_key(|f| (f.start, f.source));
+        for (i, flow) in flows.iter_mut().enumerate() {
+            flow.id = FlowId::new(i);
+        }
+        Ok(flows)
+    }
+}
+
+/// A many-to-one traffic pattern, repeated every period.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum Pattern {
+    /// N-to-1 incast: the first `nr_senders` sources each start a flow at the beginning of every
+    /// period.
+    #[default]
+    Incast,
+    /// Partition-aggregate fan-in: every period, `nr_senders` sources chosen at random respond,
+    /// each starting at a uniformly random time within `jitter` of the beginning of the period.
+    PartitionAggregate {
+        /// The maximum start offset of a response.
+        jitter: Nanosecs,
+    },
+    /// All-to-one bursts: every period, every source starts a flow at once, regardless of
+    /// `nr_senders`.
+    AllToOne,
+}
+
+/// A generator of synchronized bursts of equal-sized flows.
+#[derive(Debug, Clone, typed_builder::TypedBuilder)]
+pub struct Bursts {
+    /// The sources senders are drawn from.
+    pub sources: Vec<SourceDesc>,
+    /// The traffic pattern.
+    #[builder(default)]
+    pub pattern: Pattern,
+    /// The number of senders in each burst. Ignored by [`Pattern::AllToOne`], in which every
+    /// source sends.
+    pub nr_senders: usize,
+    /// The size of each flow.
+    #[builder(setter(into))]
+    pub size: Bytes,
+    /// The time between the beginnings of consecutive bursts.
+    #[builder(setter(into))]
+    pub period: Nanosecs,
+    /// The number of bursts.
+    pub nr_bursts: usize,
+    /// The beginning of the first burst.
+    #[builder(default, setter(into))]
+    pub start: Nanosecs,
+    /// The propagation delay from the bottleneck to the destination, added to each source's
+    /// delay to the bottleneck.
+    #[builder(setter(into))]
+    pub btl2dst: Nanosecs,
+    /// The queue index of every flow.
+    #[builder(default)]
+    pub qindex: QIndex,
+    /// The destination of every flow, if any.
+    #[builder(default, setter(strip_option))]
+    pub dst: Option<DestId>,
+    /// The ID of the first flow, so that bursts can be combined with other flows.
+    #[builder(default)]
+    pub first_id: FlowId,
+    /// The random seed.
+    #[builder(default)]
+    pub seed: u64,
+}
+
+impl Bursts {
+    /// Generates flows in order of arrival, with consecutive IDs assigned in the same order.
+    pub fn generate(&self) -> Result<Vec<FlowDesc>, WorkloadError> {
+        let nr_sources = self.sources.len();
+        if nr_sources == 0 {
+            return Err(WorkloadError::NoSources);
+        }
+        let nr_senders = match self.pattern {
+            Pattern::AllToOne => nr_sources,
+            _ => self.nr_senders,
+        };
+        if nr_senders == 0 || nr_senders > nr_sources {
+            return Err(WorkloadError::InvalidSenders);
+        }
+        let mut rng = StdRng::seed_from_u64(self.seed);
+        let mut flows = Vec::with_capacity(self.nr_bursts * nr_senders);
+        for i in 0..self.nr_bursts {
+            let begin = self.start + self.period.scale_by(i as f64);
+            let senders = match self.pattern {
+                Pattern::Incast | Pattern::AllToOne => (0..nr_senders).collect(),
+                Pattern::PartitionAggregate { .. } => {
+                    let mut senders = index::sample(&mut rng, nr_sources, nr_senders).into_vec();
+                    senders.sort_unstable();
+                    senders
+                }
+            };
+            for j in senders {
+                let source = &self.sources[j];
+                let offset = match self.pattern {
+                    Pattern::PartitionAggregate { jitter } => {
+                        Nanosecs::new(rng.gen_range(0..=jitter.into_u64()))
+                    }
+                    _ => Nanosecs::ZERO,
+                };
+                let mut flow = FlowDesc::builder()
+                    .id(FlowId::ZERO)
+                    .source(source.id)
+                    .qindex(self.qindex)
+                    .size(self.size)
+                    .start(begin + offset)
+                    .delay2dst(source.delay2btl + self.btl2dst)
+                    .build();
+                flow.dst = self.dst;
+                flows.push(flow);
+            }
+        }
+        flows.sort_by_key(|f| f.start);
+        for (i, flow) in flows.iter_mut().enumerate() {
+            flow.id = self.first_id + FlowId::new(i);
+        }
+        Ok(flows)
+    }
+}
+
+/// Workload generation errors.
+#[derive(Debug, thiserror::Error)]
+pub enum WorkloadError {
+    /// A size CDF must have nondecreasing sizes and probabilities, ending at a positive
+    /// probability.
+    #[error("A size CDF must have nondecreasing sizes and probabilities")]
+    InvalidCdf,
+
+    /// The load must be a positive fraction of a nonzero bandwidth.
+    #[error("The load must be a positive fraction of a nonzero bandwidth")]
+    InvalidLoad,
+
+    /// The arrival process parameters must be finite and nonnegative.
+    #[error("The arrival process parameters must be finite and nonnegative")]
+    InvalidArrivals,
+
+    /// The number of senders must be positive and at most the number of sources.
+    #[error("The number of senders must be positive and at most the number of sources")]
+    InvalidSenders,
+
+    /// There must be at least one source.
+    #[error("There must be at least one source")]
+    NoSources,
+
+    /// IO error.
+    #[error("IO error")]
+    Io(#[from] std::io::Error),
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        units::{Gbps, Millisecs},
+        SourceId,
+    };
+
+    use super::*;
+
+    const WEB_SEARCH: &str = "
+        # size cdf(%)
+        6000 15
+        13000 20
+        19000 30
+        33000 40
+        53000 53
+        133000 60
+        667000 70
+        1333000 80
+        3333000 90
+        6667000 97
+        20000000 100
+    ";
+
+    fn sources(n: usize) -> Vec<SourceDesc> {
+        (0..n)
+            .map(|i| {
+                SourceDesc::builder()
+                    .id(SourceId::new(i))
+                    .delay2btl(Nanosecs::new(1_000))
+                    .link_rate(Gbps::new(10))
+                    .build()
+            })
+            .collect()
+    }
+
+    fn generator(arrivals: ArrivalProcess) -> Generator {
+        Generator::builder()
+            .sources(sources(4))
+            .sizes(SizeCdf::parse(WEB_SEARCH).unwrap())
+            .arrivals(arrivals)
+            .load(0.5)
+            .bandwidth(Gbps::new(10))
+            .duration(Millisecs::new(10_000).into_us().into_ns())
+            .btl2dst(Nanosecs::new(1_000))
+            .seed(1)
+            .build()
+    }
+
+    #[test]
+    fn cdf_mean_and_samples_agree() {
+        let cdf = SizeCdf::parse(WEB_SEARCH).unwrap();
+        let mut rng = StdRng::seed_from_u64(0);
+        let n = 200_000;
+        let mean = (0..n).map(|_| cdf.sample(&mut rng).into_f64()).sum::<f64>() / n as f64;
+        assert!((mean / cdf.mean() - 1.0).abs() < 0.02);
+        assert!(SizeCdf::parse("10 0.5\n5 1").is_err());
+        assert!(SizeCdf::parse("10 x").is_err());
+    }
+
+    #[test]
+    fn offered_load_matches_target() {
+        for arrivals in [
+            ArrivalProcess::Poisson,
+            ArrivalProcess::LogNormal { sigma: 1.0 },
+        ] {
+            let gen = generator(arrivals);
+            let flows = gen.generate().unwrap();
+            assert!(flows.windows(2).all(|w| w[0].start <= w[1].start));
+            assert!(flows
+                .iter()
+                .enumerate()
+                .all(|(i, f)| f.id == FlowId::new(i)));
+            // Sizes are heavy-tailed, so the number of flows converges faster than the load
+            let capacity = gen.bandwidth.into_f64() / 8.0 * gen.duration.into_f64() / 1e9;
+            let expected = 0.5 * capacity / gen.sizes.mean();
+            assert!((flows.len() as f64 / expected - 1.0).abs() < 0.05);
+            let bytes = flows.iter().map(|f| f.size.into_f64()).sum::<f64>();
+            assert!((bytes / capacity - 0.5).abs() < 0.05);
+        }
+    }
+
+    #[test]
+    fn generation_is_seeded() {
+        let gen = generator(ArrivalProcess::Poisson);
+        let fingerprint =
+            |flows: Vec<FlowDesc>| flows.iter().map(|f| (f.size, f.start)).collect::<Vec<_>>();
+        let a = fingerprint(gen.generate().unwrap());
+        assert_eq!(a, fingerprint(gen.generate().unwrap()));
+        let other = Generator { seed: 2, ..gen };
+        assert_ne!(a, fingerprint(other.generate().unwrap()));
+    }
+
+    fn bursts(pattern: Pattern) -> Vec<FlowDesc> {
+        Bursts::builder()
+            .sources(sources(8))
+            .pattern(pattern)
+            .nr_senders(3)
+            .size(Bytes::new(10_000))
+            .period(Nanosecs::new(100_000))
+            .nr_bursts(4)
+            .start(Nanosecs::new(5_000))
+            .btl2dst(Nanosecs::new(1_000))
+            .first_id(FlowId::new(100))
+            .build()
+            .generate()
+            .unwrap()
+    }
+
+    // Returns the sorted source indices of each burst.
+    fn senders(flows: &[FlowDesc]) -> Vec<Vec<usize>> {
+        let mut bursts = vec![Vec::new(); 4];
+        for flow in flows {
+            let i = (flow.start.into_u64() - 5_000) / 100_000;
+            bursts[i as usize].push(flow.source.into_usize());
+        }
+        bursts.iter_mut().for_each(|b| b.sort_unstable());
+        bursts
+    }
+
+    #[test]
+    fn incast_repeats_the_same_senders() {
+        let flows = bursts(Pattern::Incast);
+        assert_eq!(flows.len(), 12);
+        assert!(flows
+            .iter()
+            .enumerate()
+            .all(|(i, f)| f.id == FlowId::new(100 + i) && f.size == Bytes::new(10_000)));
+        assert!(flows
+            .iter()
+            .all(|f| (f.start.into_u64() - 5_000) % 100_000 == 0));
+        assert_eq!(senders(&flows), vec![vec![0, 1, 2]; 4]);
+    }
+
+    #[test]
+    fn partition_aggregate_jitters_starts() {
+        let jitter = Nanosecs::new(10_000);
+        let flows = bursts(Pattern::PartitionAggregate { jitter });
+        assert!(flows.windows(2).all(|w| w[0].start <= w[1].start));
+        assert!(flows
+            .iter()
+            .all(|f| (f.start.into_u64() - 5_000) % 100_000 <= jitter.into_u64()));
+        let senders = senders(&flows);
+        assert!(senders
+            .iter()
+            .all(|s| s.len() == 3 && s.windows(2).all(|w| w[0] < w[1])));
+        assert!(senders.iter().any(|s| *s != senders[0]));
+    }
+
+    #[test]
+    fn all_to_one_sends_from_every_source() {
+        let flows = bursts(Pattern::AllToOne);
+        assert_eq!(flows.len(), 32);
+        assert_eq!(senders(&flows), vec![(0..8).collect::<Vec<_>>(); 4]);
+    }
+}
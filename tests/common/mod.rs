@@ -0,0 +1,67 @@
+// Helpers shared by the integration tests, each of which uses only some of them
+#![allow(dead_code)]
+
+use minim::{
+    cc::CcKind,
+    units::{Bytes, Gbps, Kilobytes, Mbps, Microsecs, Nanosecs},
+    Config, FlowDesc, FlowId, Record, SourceDesc, SourceId,
+};
+
+pub const NR_SOURCES: usize = 8;
+
+// An 8-to-1 incast of long flows followed by a few short flows, so that every algorithm has to
+// react to congestion.
+fn incast() -> (Vec<SourceDesc>, Vec<FlowDesc>) {
+    let sources = (0..NR_SOURCES)
+        .map(|i| {
+            SourceDesc::builder()
+                .id(SourceId::new(i))
+                .delay2btl(Nanosecs::new(1_000))
+                .link_rate(Gbps::new(10))
+                .build()
+        })
+        .collect();
+    let flows = (0..2 * NR_SOURCES)
+        .map(|i| {
+            let (size, start) = if i < NR_SOURCES {
+                (Bytes::new(1_000_000), Nanosecs::ZERO)
+            } else {
+                (Bytes::new(10_000), Microsecs::new(100).into_ns())
+            };
+            FlowDesc::builder()
+                .id(FlowId::new(i))
+                .source(SourceId::new(i % NR_SOURCES))
+                .size(size)
+                .start(start)
+                .delay2dst(Nanosecs::new(2_000))
+                .build()
+        })
+        .collect();
+    (sources, flows)
+}
+
+pub fn config(cc: CcKind) -> Config {
+    let (sources, flows) = incast();
+    Config::builder()
+        .bandwidth(Gbps::new(10))
+        .sources(sources)
+        .flows(flows)
+        .quanta(vec![Bytes::new(1000)])
+        .window(Kilobytes::new(18))
+        .dctcp_marking_threshold(Kilobytes::new(30))
+        .dctcp_gain(0.0625)
+        .dctcp_ai(Mbps::new(615))
+        .cc(cc)
+        .sz_pktmax(Bytes::new(1000))
+        .sz_pkthdr(Bytes::new(48))
+        .build()
+}
+
+pub fn check_complete(mut records: Vec<Record>) -> Vec<Record> {
+    assert_eq!(records.len(), 2 * NR_SOURCES);
+    for record in &records {
+        assert!(record.fct >= record.ideal);
+    }
+    records.sort_by_key(|r| r.id);
+    records
+}
@@ -0,0 +1,391 @@
+use minim::{
+    cc::{CcKind, SwiftConfig, TimelyConfig},
+    units::{Bytes, Gbps, Kilobytes, Mbps, Microsecs, Nanosecs},
+    AckPath, AqmKind, BandwidthTrace, CoDelConfig, Config, DelayedAck, LinkDesc, LinkId,
+    LossRecovery, PieConfig, Record, RedConfig, Retransmission,
+};
+
+mod common;
+
+use common::{check_complete, config, NR_SOURCES};
+
+#[test]
+fn dctcp_completes() -> anyhow::Result<()> {
+    check_complete(minim::run(config(CcKind::Dctcp))?);
+    Ok(())
+}
+
+// The FCTs produced by the DCTCP implementation that predates the `CongestionControl` trait, which
+// the trait-based implementation must reproduce exactly.
+#[test]
+fn dctcp_matches_inline_implementation() -> anyhow::Result<()> {
+    let expected = [
+        6658881, 6584920, 6843598, 6196627, 6577678, 6777674, 6819702, 6804671, 128756, 126242,
+        134622, 137136, 127918, 136298, 124566, 122890,
+    ];
+    let records = check_complete(minim::run(config(CcKind::Dctcp))?);
+    let fcts = records.iter().map(|r| r.fct.into_u64()).collect::<Vec<_>>();
+    assert_eq!(fcts, expected);
+    Ok(())
+}
+
+// Runs the incast with window-based DCTCP, returning the mean bottleneck queue and the time the
+// last flow completes.
+fn window_dctcp(pacing: bool, threshold: Kilobytes) -> anyhow::Result<(Bytes, Nanosecs)> {
+    let mut cfg = config(CcKind::WindowDctcp);
+    cfg.dctcp_pacing = pacing;
+    cfg.dctcp_marking_threshold = threshold.into();
+    let output = minim::run_with_stats(cfg)?;
+    let records = check_complete(output.records);
+    let last = records.iter().map(|r| r.start + r.fct).max().unwrap();
+    Ok((output.queues[0].mean_occupancy, last))
+}
+
+#[test]
+fn window_dctcp_holds_queue_at_marking_threshold() -> anyhow::Result<()> {
+    for pacing in [false, true] {
+        // Without marks, the windows fill the queue
+        let (_, unmarked) = window_dctcp(pacing, Kilobytes::new(1_000))?;
+        for k in [15, 30, 60] {
+            let (queue, last) = window_dctcp(pacing, Kilobytes::new(k))?;
+            let ratio = queue.into_f64() / Kilobytes::new(k).into_bytes().into_f64();
+            assert!((0.8..1.2).contains(&ratio));
+            // The queue never runs dry, so the link stays busy
+            assert!(last.into_f64() < 1.05 * unmarked.into_f64());
+        }
+    }
+    Ok(())
+}
+
+// A lone window-limited flow from a 100 Gbps NIC, returning its FCT and the largest queue at the
+// 10 Gbps bottleneck.
+fn fast_nic(pacing: bool) -> anyhow::Result<(Nanosecs, Bytes)> {
+    let mut cfg = config(CcKind::WindowDctcp);
+    cfg.dctcp_pacing = pacing;
+    // Less than the 7.5 KB BDP, so the window never builds a standing queue
+    cfg.window = Kilobytes::new(4).into();
+    cfg.flows.truncate(1);
+    cfg.sources[0].link_rate = Gbps::new(100).into();
+    let output = minim::run_with_stats(cfg)?;
+    Ok((output.records[0].fct, output.queues[0].max_occupancy))
+}
+
+#[test]
+fn pacing_spreads_window_over_rtt() -> anyhow::Result<()> {
+    // Without pacing, the window leaves the NIC as a burst that queues at the bottleneck
+    let (bursty_fct, bursty_queue) = fast_nic(false)?;
+    let (paced_fct, paced_queue) = fast_nic(true)?;
+    assert!(bursty_queue > Bytes::new(2 * 1048));
+    assert!(paced_queue <= Bytes::new(1048));
+    // Either way, the flow sends one window per RTT
+    assert!((paced_fct.into_f64() / bursty_fct.into_f64() - 1.0).abs() < 0.01);
+    Ok(())
+}
+
+fn red(kmin: u64, kmax: u64, pmax: f64) -> RedConfig {
+    RedConfig::builder()
+        .kmin(Kilobytes::new(kmin))
+        .kmax(Kilobytes::new(kmax))
+        .pmax(pmax)
+        .build()
+}
+
+// Replaces the short flows with single-packet probes that arrive once the long flows have
+// settled, returning the records and the mean queue size seen by the probes.
+fn probe_queue(mut cfg: Config) -> anyhow::Result<(Vec<Record>, Bytes)> {
+    for (i, flow) in cfg.flows[NR_SOURCES..].iter_mut().enumerate() {
+        flow.size = Bytes::new(1_000);
+        flow.start = Microsecs::new(3_000 + 250 * i as u64).into_ns();
+    }
+    let records = check_complete(minim::run(cfg)?);
+    let total = records[NR_SOURCES..]
+        .iter()
+        .map(|r| Gbps::new(10).into_bps().width(r.fct - r.ideal))
+        .sum::<Bytes>();
+    Ok((records, total.scale_by(1.0 / NR_SOURCES as f64)))
+}
+
+#[test]
+fn dcqcn_holds_queue_between_red_thresholds() -> anyhow::Result<()> {
+    let mut queues = Vec::new();
+    for (kmin, kmax) in [(10, 50), (50, 100), (100, 200)] {
+        // A window large enough to never limit DCQCN
+        let mut cfg = config(CcKind::Dcqcn);
+        cfg.window = Bytes::new(1_000_000);
+        cfg.red = Some(red(kmin, kmax, 0.2));
+        let (_, queue) = probe_queue(cfg)?;
+        assert!(Kilobytes::new(kmin).into_bytes() < queue);
+        assert!(queue < Kilobytes::new(kmax).into_bytes());
+        queues.push(queue);
+    }
+    assert!(queues.windows(2).all(|w| w[0] < w[1]));
+    Ok(())
+}
+
+#[test]
+fn red_is_reproducible() -> anyhow::Result<()> {
+    let run = |seed| -> anyhow::Result<Vec<Record>> {
+        let mut cfg = config(CcKind::Dcqcn);
+        cfg.red = Some(red(10, 50, 0.2));
+        cfg.seed = seed;
+        Ok(check_complete(minim::run(cfg)?))
+    };
+    let fcts = |records: Vec<Record>| records.iter().map(|r| r.fct).collect::<Vec<_>>();
+    assert_eq!(fcts(run(1)?), fcts(run(1)?));
+    Ok(())
+}
+
+#[test]
+fn red_probability_is_validated() {
+    for pmax in [-0.1, 1.5, f64::NAN] {
+        let mut cfg = config(CcKind::Dcqcn);
+        cfg.red = Some(red(10, 50, pmax));
+        assert!(minim::run(cfg).is_err());
+    }
+}
+
+#[test]
+fn aqm_intervals_are_validated() {
+    let zero = Nanosecs::ZERO;
+    for aqm in [
+        AqmKind::CoDel(CoDelConfig::builder().target(zero).build()),
+        AqmKind::CoDel(CoDelConfig::builder().interval(zero).build()),
+        AqmKind::Pie(PieConfig::builder().t_update(zero).build()),
+    ] {
+        let mut cfg = config(CcKind::Dctcp);
+        cfg.aqm = vec![aqm];
+        assert!(minim::run(cfg).is_err());
+    }
+}
+
+#[test]
+fn hpcc_keeps_queue_near_zero() -> anyhow::Result<()> {
+    let (_, dctcp_queue) = probe_queue(config(CcKind::Dctcp))?;
+    let (records, queue) = probe_queue(config(CcKind::Hpcc))?;
+    // DCTCP keeps a standing queue around its marking threshold, but HPCC holds less than a packet
+    assert!(dctcp_queue > Kilobytes::new(10).into_bytes());
+    assert!(queue < Bytes::new(1_000));
+    // The long flows still use most of the link
+    let long = &records[..NR_SOURCES];
+    let bytes = long.iter().map(|r| r.size).sum::<Bytes>();
+    let last = long.iter().map(|r| r.start + r.fct).max().unwrap();
+    assert!(bytes.into_f64() > 0.8 * Gbps::new(10).into_bps().width(last).into_f64());
+    Ok(())
+}
+
+#[test]
+fn timely_queue_grows_with_t_low() -> anyhow::Result<()> {
+    let mut queues = Vec::new();
+    for t_low in [20, 50, 100] {
+        let t_low = Microsecs::new(t_low).into_ns();
+        let mut cfg = config(CcKind::Timely);
+        cfg.timely = TimelyConfig::builder().t_low(t_low).build();
+        let (_, queue) = probe_queue(cfg)?;
+        // The rate keeps increasing until the RTT reaches `t_low`, and the RTT gradient keeps it
+        // from growing much further
+        assert!(queue < Gbps::new(10).into_bps().width(t_low));
+        queues.push(queue);
+    }
+    assert!(queues.windows(2).all(|w| w[0] < w[1]));
+    Ok(())
+}
+
+#[test]
+fn swift_holds_delay_near_target() -> anyhow::Result<()> {
+    for target in [25, 50, 100] {
+        let target = Microsecs::new(target).into_ns();
+        let mut cfg = config(CcKind::Swift);
+        // Large enough that the window never limits the queue
+        cfg.window = Kilobytes::new(100).into();
+        cfg.swift = SwiftConfig::builder().base_target(target).build();
+        let (_, queue) = probe_queue(cfg)?;
+        let expected = Gbps::new(10).into_bps().width(target);
+        assert!((queue.into_f64() / expected.into_f64() - 1.0).abs() < 0.2);
+    }
+    Ok(())
+}
+
+#[test]
+fn hpcc_reacts_to_first_hop_bottleneck() -> anyhow::Result<()> {
+    // The incast crosses the top-level bottleneck and then a much faster link
+    let mut cfg = config(CcKind::Hpcc);
+    cfg.links = vec![LinkDesc::builder()
+        .id(LinkId::ONE)
+        .bandwidth(Gbps::new(100))
+        .delay(Nanosecs::new(1_000))
+        .dctcp_marking_threshold(Kilobytes::new(30))
+        .build()];
+    for flow in &mut cfg.flows {
+        flow.path = vec![LinkId::ZERO, LinkId::ONE];
+        flow.delay2dst += Nanosecs::new(1_000);
+    }
+    let output = minim::run_with_stats(cfg)?;
+    check_complete(output.records);
+    // The telemetry of the first link keeps its queue short, even though the last link is idle
+    assert!(output.links[0].queues[0].mean_occupancy < Bytes::new(10_000));
+    Ok(())
+}
+
+#[test]
+fn hpcc_adapts_to_capacity_drop() -> anyhow::Result<()> {
+    // A lone flow's link drops to a fifth of its capacity early on
+    let mut cfg = config(CcKind::Hpcc);
+    cfg.flows.truncate(1);
+    cfg.flows[0].size = Bytes::new(2_000_000);
+    cfg.bandwidth_trace =
+        BandwidthTrace::new(vec![(Microsecs::new(50).into_ns(), Gbps::new(2).into())]);
+    let output = minim::run_with_stats(cfg)?;
+    assert_eq!(output.records.len(), 1);
+    // The telemetry reports the reduced capacity, so HPCC shrinks its window to match it instead
+    // of keeping a standing queue
+    assert!(output.queues[0].mean_occupancy < Bytes::new(1_000));
+    Ok(())
+}
+
+// Runs the incast with its ACKs crossing a 500 Mbps reverse link, which cannot keep up with one
+// 64 B ACK per 1 KB packet at 10 Gbps, returning the largest ACK queue at that link.
+fn ack_queue(cc: CcKind, delayed_ack: Option<DelayedAck>) -> anyhow::Result<Bytes> {
+    let mut cfg = config(cc);
+    cfg.links = vec![LinkDesc::builder()
+        .id(LinkId::ONE)
+        .bandwidth(Mbps::new(500))
+        .delay(Nanosecs::new(1_000))
+        .dctcp_marking_threshold(Kilobytes::new(30))
+        .build()];
+    cfg.ack_path = Some(AckPath::builder().link(LinkId::ONE).build());
+    cfg.delayed_ack = delayed_ack;
+    let output = minim::run_with_stats(cfg)?;
+    check_complete(output.records);
+    Ok(output.links[1].queues[0].max_occupancy)
+}
+
+#[test]
+fn delayed_acks_thin_out_ack_traffic() -> anyhow::Result<()> {
+    let delayed_ack = DelayedAck::builder().nr_packets(4).build();
+    for cc in [
+        CcKind::Dctcp,
+        CcKind::Dcqcn,
+        CcKind::Hpcc,
+        CcKind::Timely,
+        CcKind::Swift,
+    ] {
+        // A quarter of the ACKs fit on the reverse link, so they no longer queue up
+        let delayed = ack_queue(cc, Some(delayed_ack))?;
+        assert!(delayed < ack_queue(cc, None)?);
+        assert!(delayed <= Bytes::new(8 * 64));
+    }
+    // Losses are still recovered from
+    let mut cfg = config(CcKind::Dctcp);
+    cfg.delayed_ack = Some(delayed_ack);
+    cfg.queue_buffer = Some(Kilobytes::new(20).into());
+    cfg.loss_recovery = LossRecovery::builder()
+        .mode(Retransmission::Selective)
+        .rto_min(Microsecs::new(100).into_ns())
+        .build();
+    let records = check_complete(minim::run(cfg)?);
+    assert!(records.iter().any(|r| r.drops > 0));
+    Ok(())
+}
+
+// Runs a single 100 KB flow, returning its FCT.
+fn lone_flow_fct(
+    cc: CcKind,
+    initial_rate: Option<Gbps>,
+    initial_window: Option<Bytes>,
+) -> anyhow::Result<Nanosecs> {
+    let mut cfg = config(cc);
+    cfg.flows.truncate(1);
+    cfg.flows[0].size = Kilobytes::new(100).into();
+    cfg.initial_rate = initial_rate.map(Into::into);
+    cfg.initial_window = initial_window;
+    let records = minim::run(cfg)?;
+    assert_eq!(records.len(), 1);
+    Ok(records[0].fct)
+}
+
+#[test]
+fn slow_start_doubles_window_every_rtt() -> anyhow::Result<()> {
+    // The base RTT is twice the 3 us propagation delay, which holds about 7.5 KB at 10 Gbps
+    let rtt = Nanosecs::new(6_000);
+    let baseline = lone_flow_fct(CcKind::Dctcp, None, None)?;
+    let fcts = [1_000, 2_000, 4_000, 8_000]
+        .into_iter()
+        .map(|w| lone_flow_fct(CcKind::Dctcp, None, Some(Bytes::new(w))))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    // Halving the initial window costs at most one more RTT to reach the BDP
+    assert!(fcts.windows(2).all(|w| w[0] > w[1] && w[0] - w[1] <= rtt));
+    assert_eq!(fcts[3], baseline);
+    assert!(fcts[0] - baseline <= rtt.scale_by(3.0));
+    Ok(())
+}
+
+#[test]
+fn low_initial_rate_ramps_up() -> anyhow::Result<()> {
+    // At a constant 2 Gbps, the flow would take more than 400 us
+    let at_initial_rate = Gbps::new(2).into_bps().length(Bytes::new(104_800));
+    for cc in [
+        CcKind::Dctcp,
+        CcKind::Dcqcn,
+        CcKind::Hpcc,
+        CcKind::Timely,
+        CcKind::Swift,
+    ] {
+        let baseline = lone_flow_fct(cc, None, None)?;
+        let fct = lone_flow_fct(cc, Some(Gbps::new(2)), Some(Bytes::new(2_000)))?;
+        assert!(baseline < fct && fct < at_initial_rate);
+    }
+    // A lower initial rate takes longer to ramp up from
+    let fcts = [1, 2, 5]
+        .into_iter()
+        .map(|g| lone_flow_fct(CcKind::Dctcp, Some(Gbps::new(g)), None))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    assert!(fcts.windows(2).all(|w| w[0] > w[1]));
+    Ok(())
+}
+
+#[test]
+fn d2tcp_reports_deadline_misses() -> anyhow::Result<()> {
+    let mut cfg = config(CcKind::D2tcp);
+    for flow in &mut cfg.flows {
+        flow.deadline = Some(Microsecs::new(500).into_ns());
+    }
+    let records = check_complete(minim::run(cfg)?);
+    for record in &records {
+        let met = record.met_deadline().unwrap();
+        let miss = record.deadline_miss().unwrap();
+        assert_eq!(met, miss == Nanosecs::ZERO);
+        assert_eq!(met, record.fct <= Microsecs::new(500).into_ns());
+    }
+    // The short flows make their deadlines, but the 1 MB flows cannot
+    assert!(records[NR_SOURCES..]
+        .iter()
+        .all(|r| r.met_deadline() == Some(true)));
+    assert!(records[..NR_SOURCES]
+        .iter()
+        .all(|r| r.met_deadline() == Some(false)));
+    Ok(())
+}
+
+#[test]
+fn d2tcp_favors_near_deadlines() -> anyhow::Result<()> {
+    // Half the long flows have a tight deadline, the other half a loose one
+    let with_deadlines = |cc| {
+        let mut cfg = config(cc);
+        for (i, flow) in cfg.flows[..NR_SOURCES].iter_mut().enumerate() {
+            let deadline = if i < NR_SOURCES / 2 { 4_000 } else { 100_000 };
+            flow.deadline = Some(Microsecs::new(deadline).into_ns());
+        }
+        minim::run(cfg).map(check_complete)
+    };
+    // DCTCP ignores deadlines
+    let dctcp = with_deadlines(CcKind::Dctcp)?;
+    let baseline = check_complete(minim::run(config(CcKind::Dctcp))?);
+    assert!(dctcp.iter().zip(&baseline).all(|(a, b)| a.fct == b.fct));
+    // D2TCP backs off less for the tight deadlines, so those flows finish first
+    let d2tcp = with_deadlines(CcKind::D2tcp)?;
+    let (tight, loose) = d2tcp[..NR_SOURCES].split_at(NR_SOURCES / 2);
+    let tight_max = tight.iter().map(|r| r.fct).max().unwrap();
+    let loose_min = loose.iter().map(|r| r.fct).min().unwrap();
+    assert!(tight_max < loose_min);
+    Ok(())
+}
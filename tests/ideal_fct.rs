@@ -1,6 +1,6 @@
 use minim::{
     units::{Bytes, Gbps, Kilobytes, Mbps, Nanosecs, Secs},
-    Config, FlowDesc, FlowId, QIndex, SourceDesc, SourceId,
+    Config, FlowDesc, FlowId, LinkDesc, LinkId, QIndex, SourceDesc, SourceId,
 };
 
 // Make sure FCTs match up for short flows and long flows.
@@ -12,22 +12,22 @@ fn ideal_fct() -> anyhow::Result<()> {
         .link_rate(Gbps::new(10))
         .build();
     let flows = vec![
-        FlowDesc {
-            id: FlowId::new(0),
-            source: SourceId::ZERO,
-            qindex: QIndex::ZERO,
-            size: Bytes::new(100),
-            start: Secs::new(1).into_ns(),
-            delay2dst: Nanosecs::new(2_000),
-        },
-        FlowDesc {
-            id: FlowId::new(1),
-            source: SourceId::ZERO,
-            qindex: QIndex::ZERO,
-            size: Bytes::new(1_000_000),
-            start: Secs::new(2).into_ns(),
-            delay2dst: Nanosecs::new(2_000),
-        },
+        FlowDesc::builder()
+            .id(FlowId::new(0))
+            .source(SourceId::ZERO)
+            .qindex(QIndex::ZERO)
+            .size(Bytes::new(100))
+            .start(Secs::new(1).into_ns())
+            .delay2dst(Nanosecs::new(2_000))
+            .build(),
+        FlowDesc::builder()
+            .id(FlowId::new(1))
+            .source(SourceId::ZERO)
+            .qindex(QIndex::ZERO)
+            .size(Bytes::new(1_000_000))
+            .start(Secs::new(2).into_ns())
+            .delay2dst(Nanosecs::new(2_000))
+            .build(),
     ];
     let cfg = Config::builder()
         .bandwidth(Gbps::new(40))
@@ -47,3 +47,60 @@ fn ideal_fct() -> anyhow::Result<()> {
     }
     Ok(())
 }
+
+// The same, but with every flow crossing three links.
+#[test]
+fn multi_hop_ideal_fct() -> anyhow::Result<()> {
+    let source = SourceDesc::builder()
+        .id(SourceId::ZERO)
+        .delay2btl(Nanosecs::new(1_000))
+        .link_rate(Gbps::new(10))
+        .build();
+    let links = (1..3)
+        .map(|i| {
+            LinkDesc::builder()
+                .id(LinkId::new(i))
+                .bandwidth(Gbps::new(40))
+                .delay(Nanosecs::new(500))
+                .dctcp_marking_threshold(Kilobytes::new(300))
+                .build()
+        })
+        .collect();
+    let path = (0..3).map(LinkId::new).collect::<Vec<_>>();
+    let flows = vec![
+        FlowDesc::builder()
+            .id(FlowId::new(0))
+            .source(SourceId::ZERO)
+            .size(Bytes::new(100))
+            .start(Secs::new(1).into_ns())
+            .delay2dst(Nanosecs::new(3_000))
+            .path(path.clone())
+            .build(),
+        FlowDesc::builder()
+            .id(FlowId::new(1))
+            .source(SourceId::ZERO)
+            .size(Bytes::new(1_000_000))
+            .start(Secs::new(2).into_ns())
+            .delay2dst(Nanosecs::new(3_000))
+            .path(path)
+            .build(),
+    ];
+    let cfg = Config::builder()
+        .bandwidth(Gbps::new(40))
+        .links(links)
+        .sources(vec![source])
+        .flows(flows)
+        .quanta(vec![Bytes::new(1000)])
+        .window(Kilobytes::new(100))
+        .dctcp_marking_threshold(Kilobytes::new(300))
+        .dctcp_gain(0.0625)
+        .dctcp_ai(Mbps::new(615))
+        .sz_pktmax(Bytes::new(1000))
+        .sz_pkthdr(Bytes::new(48))
+        .build();
+    let records = minim::run(cfg)?;
+    for record in records {
+        assert_eq!(record.fct, record.ideal);
+    }
+    Ok(())
+}
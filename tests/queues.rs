@@ -0,0 +1,240 @@
+use minim::{
+    cc::CcKind,
+    units::{Bytes, Gbps, Kilobytes, Microsecs, Nanosecs},
+    AqmKind, CoDelConfig, FlowDesc, FlowId, LossRecovery, PfcConfig, PieConfig, QIndex, Record,
+    Retransmission, SchedulerKind, SharedBuffer, SourceDesc, SourceId,
+};
+
+mod common;
+
+use common::{check_complete, config, NR_SOURCES};
+
+fn lossy(mode: Retransmission) -> anyhow::Result<Vec<Record>> {
+    let mut cfg = config(CcKind::Dctcp);
+    cfg.queue_buffer = Some(Kilobytes::new(20).into());
+    cfg.loss_recovery = LossRecovery::builder()
+        .mode(mode)
+        .rto_min(Microsecs::new(100).into_ns())
+        .build();
+    let records = check_complete(minim::run(cfg)?);
+    assert!(records.iter().any(|r| r.drops > 0));
+    for record in &records {
+        assert!(record.drops == 0 || record.retransmits > 0);
+    }
+    Ok(records)
+}
+
+#[test]
+fn go_back_n_recovers_drops() -> anyhow::Result<()> {
+    lossy(Retransmission::GoBackN)?;
+    Ok(())
+}
+
+#[test]
+fn selective_recovers_drops() -> anyhow::Result<()> {
+    let gbn = lossy(Retransmission::GoBackN)?;
+    let sel = lossy(Retransmission::Selective)?;
+    let total = |records: &[Record]| records.iter().map(|r| r.retransmits).sum::<usize>();
+    assert!(total(&sel) <= total(&gbn));
+    Ok(())
+}
+
+#[test]
+fn shared_buffer_limits_each_queue() -> anyhow::Result<()> {
+    let mut cfg = config(CcKind::Dctcp);
+    cfg.quanta = vec![Bytes::new(1000); 2];
+    for flow in &mut cfg.flows {
+        flow.qindex = QIndex::new(flow.id.into_usize() % 2);
+    }
+    cfg.shared_buffer = Some(
+        SharedBuffer::builder()
+            .size(Kilobytes::new(30))
+            .alphas(vec![1.0, 1.0])
+            .build(),
+    );
+    let output = minim::run_with_stats(cfg)?;
+    let records = check_complete(output.records);
+    assert_eq!(output.queues.len(), 2);
+    // A queue stops growing once it holds half of the buffer
+    for queue in &output.queues {
+        assert!(queue.max_occupancy <= Kilobytes::new(15).into_bytes() + Bytes::new(1048));
+        assert!(queue.mean_occupancy <= queue.max_occupancy);
+    }
+    let queue_drops = output.queues.iter().map(|q| q.drops).sum::<usize>();
+    let flow_drops = records.iter().map(|r| r.drops).sum::<usize>();
+    assert!(queue_drops > 0);
+    assert_eq!(queue_drops, flow_drops);
+    Ok(())
+}
+
+#[test]
+fn pfc_prevents_drops() -> anyhow::Result<()> {
+    let mut cfg = config(CcKind::Dctcp);
+    // Leave enough headroom for the packets sent while the pause frames propagate
+    cfg.queue_buffer = Some(Kilobytes::new(50).into());
+    cfg.pfc = Some(
+        PfcConfig::builder()
+            .xoff(Kilobytes::new(15))
+            .xon(Kilobytes::new(10))
+            .build(),
+    );
+    let output = minim::run_with_stats(cfg)?;
+    let records = check_complete(output.records);
+    assert!(records.iter().all(|r| r.drops == 0));
+    assert_eq!(output.sources.len(), NR_SOURCES);
+    assert!(output
+        .sources
+        .iter()
+        .all(|s| s.nr_pauses > 0 && s.paused_for > Nanosecs::ZERO));
+    Ok(())
+}
+
+#[test]
+fn pfc_skips_finished_sources() -> anyhow::Result<()> {
+    // An extra source sends a short flow through the same queue class long before the incast
+    let mut cfg = config(CcKind::Dctcp);
+    cfg.queue_buffer = Some(Kilobytes::new(50).into());
+    cfg.pfc = Some(
+        PfcConfig::builder()
+            .xoff(Kilobytes::new(15))
+            .xon(Kilobytes::new(10))
+            .build(),
+    );
+    for flow in &mut cfg.flows {
+        flow.start += Microsecs::new(200).into_ns();
+    }
+    let early = SourceId::new(NR_SOURCES);
+    cfg.sources.push(
+        SourceDesc::builder()
+            .id(early)
+            .delay2btl(Nanosecs::new(1_000))
+            .link_rate(Gbps::new(10))
+            .build(),
+    );
+    cfg.flows.insert(
+        0,
+        FlowDesc::builder()
+            .id(FlowId::new(2 * NR_SOURCES))
+            .source(early)
+            .size(Kilobytes::new(10))
+            .start(Nanosecs::ZERO)
+            .delay2dst(Nanosecs::new(2_000))
+            .build(),
+    );
+    let output = minim::run_with_stats(cfg)?;
+    assert_eq!(output.records.len(), 2 * NR_SOURCES + 1);
+    // Only the sources with packets in the queue are paused
+    for stats in &output.sources {
+        if stats.id == early {
+            assert_eq!(stats.nr_pauses, 0);
+            assert_eq!(stats.paused_for, Nanosecs::ZERO);
+        } else {
+            assert!(stats.nr_pauses > 0);
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn priority_queues_favor_short_flows() -> anyhow::Result<()> {
+    let short_fct = |priority_queues| -> anyhow::Result<u64> {
+        let mut cfg = config(CcKind::Dctcp);
+        cfg.priority_queues = priority_queues;
+        let records = check_complete(minim::run(cfg)?);
+        Ok(records[NR_SOURCES..].iter().map(|r| r.fct.into_u64()).sum())
+    };
+    assert!(short_fct(true)? < short_fct(false)?);
+    Ok(())
+}
+
+#[test]
+fn mlfq_demotes_long_flows() -> anyhow::Result<()> {
+    let mut cfg = config(CcKind::Dctcp);
+    cfg.quanta = vec![Bytes::new(1000); 3];
+    cfg.scheduler = SchedulerKind::StrictPriority;
+    cfg.mlfq_thresholds = vec![Kilobytes::new(10).into(), Kilobytes::new(100).into()];
+    let records = check_complete(minim::run(cfg)?);
+    for record in &records {
+        let nr_levels = if record.size > Kilobytes::new(100).into_bytes() {
+            3
+        } else {
+            1
+        };
+        let (visited, rest) = record.queue_times.split_at(nr_levels);
+        assert!(visited.iter().all(|&t| t > Nanosecs::ZERO));
+        assert!(rest.iter().all(|&t| t == Nanosecs::ZERO));
+        assert_eq!(visited.iter().copied().sum::<Nanosecs>(), record.fct);
+    }
+    Ok(())
+}
+
+#[test]
+fn mlfq_keeps_flows_demoted_after_losses() -> anyhow::Result<()> {
+    // A lone flow bursts into a slower bottleneck with a small buffer, so it loses packets
+    // before it crosses the threshold and rewinds after it has crossed it
+    for mode in [Retransmission::GoBackN, Retransmission::Selective] {
+        let mut cfg = config(CcKind::Dctcp);
+        cfg.bandwidth = Gbps::new(1).into();
+        cfg.flows = vec![FlowDesc::builder()
+            .id(FlowId::ZERO)
+            .source(SourceId::ZERO)
+            .size(Kilobytes::new(100))
+            .start(Nanosecs::ZERO)
+            .delay2dst(Nanosecs::new(2_000))
+            .build()];
+        cfg.quanta = vec![Bytes::new(1000); 2];
+        cfg.scheduler = SchedulerKind::StrictPriority;
+        cfg.mlfq_thresholds = vec![Kilobytes::new(10).into()];
+        cfg.queue_buffer = Some(Kilobytes::new(5).into());
+        cfg.loss_recovery = LossRecovery::builder()
+            .mode(mode)
+            .rto_min(Microsecs::new(100).into_ns())
+            .build();
+        let records = minim::run(cfg)?;
+        assert!(records[0].retransmits > 0);
+        // The flow leaves the first queue after sending ten packets back to back and never
+        // returns to it
+        let pkt_time = Gbps::new(10).into_bps().length(Bytes::new(1048));
+        assert_eq!(records[0].queue_times[0], pkt_time.scale_by(10.0));
+    }
+    Ok(())
+}
+
+// Without ECN marking, DCTCP only backs off on loss, so AQM has to keep the queue short.
+fn with_aqm(aqm: Option<AqmKind>) -> anyhow::Result<(Vec<Record>, Bytes)> {
+    let mut cfg = config(CcKind::Dctcp);
+    cfg.dctcp_marking_threshold = Bytes::MAX;
+    cfg.aqm = aqm.into_iter().collect();
+    cfg.loss_recovery = LossRecovery::builder()
+        .mode(Retransmission::Selective)
+        .rto_min(Microsecs::new(100).into_ns())
+        .build();
+    let output = minim::run_with_stats(cfg)?;
+    Ok((
+        check_complete(output.records),
+        output.queues[0].mean_occupancy,
+    ))
+}
+
+#[test]
+fn codel_keeps_queue_short() -> anyhow::Result<()> {
+    let (_, baseline) = with_aqm(None)?;
+    let (records, occupancy) = with_aqm(Some(AqmKind::CoDel(CoDelConfig::default())))?;
+    assert!(records.iter().any(|r| r.drops > 0));
+    assert!(occupancy < baseline);
+    // With ECN, CoDel marks instead of dropping
+    let cfg = CoDelConfig::builder().ecn(true).build();
+    let (records, occupancy) = with_aqm(Some(AqmKind::CoDel(cfg)))?;
+    assert!(records.iter().all(|r| r.drops == 0));
+    assert!(occupancy < baseline);
+    Ok(())
+}
+
+#[test]
+fn pie_keeps_queue_short() -> anyhow::Result<()> {
+    let (_, baseline) = with_aqm(None)?;
+    let (records, occupancy) = with_aqm(Some(AqmKind::Pie(PieConfig::default())))?;
+    assert!(records.iter().any(|r| r.drops > 0));
+    assert!(occupancy < baseline);
+    Ok(())
+}
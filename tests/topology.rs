@@ -0,0 +1,188 @@
+use minim::{
+    cc::CcKind,
+    units::{BitsPerSec, Bytes, Gbps, Kilobytes, Microsecs, Nanosecs},
+    AckPath, BandwidthTrace, DestDesc, DestId, FlowDesc, FlowId, LinkDesc, LinkId, LinkStats,
+    Output, Record, SourceDesc, SourceId,
+};
+
+mod common;
+
+use common::{check_complete, config, NR_SOURCES};
+
+// A parking-lot topology: one long flow crosses a chain of three links, each of which is shared
+// with a one-hop cross flow.
+#[test]
+fn parking_lot_penalizes_long_flow() -> anyhow::Result<()> {
+    let mut cfg = config(CcKind::Dctcp);
+    cfg.links = (1..3)
+        .map(|i| {
+            LinkDesc::builder()
+                .id(LinkId::new(i))
+                .bandwidth(Gbps::new(10))
+                .delay(Nanosecs::new(1_000))
+                .dctcp_marking_threshold(Kilobytes::new(30))
+                .build()
+        })
+        .collect();
+    cfg.sources.truncate(4);
+    cfg.flows = (0..4)
+        .map(|i| {
+            let (path, delay2dst) = if i == 0 {
+                ((0..3).map(LinkId::new).collect(), 4_000)
+            } else {
+                (vec![LinkId::new(i - 1)], 2_000)
+            };
+            FlowDesc::builder()
+                .id(FlowId::new(i))
+                .source(SourceId::new(i))
+                .size(Bytes::new(1_000_000))
+                .start(Nanosecs::ZERO)
+                .delay2dst(Nanosecs::new(delay2dst))
+                .path(path)
+                .build()
+        })
+        .collect();
+    let output = minim::run_with_stats(cfg)?;
+    let mut records = output.records;
+    records.sort_by_key(|r| r.id);
+    assert_eq!(records.len(), 4);
+    assert!(records.iter().all(|r| r.fct >= r.ideal));
+    // The long flow is marked at every hop, so it backs off the most
+    assert!(records[1..].iter().all(|r| r.fct < records[0].fct));
+    assert_eq!(output.links.len(), 3);
+    assert!(output
+        .links
+        .iter()
+        .all(|l| l.queues[0].max_occupancy > Bytes::ZERO));
+    Ok(())
+}
+
+#[test]
+fn outage_delays_flows() -> anyhow::Result<()> {
+    let baseline = check_complete(minim::run(config(CcKind::Dctcp))?);
+    let mut cfg = config(CcKind::Dctcp);
+    cfg.bandwidth_trace = BandwidthTrace::new(vec![
+        (Microsecs::new(200).into_ns(), BitsPerSec::ZERO),
+        (Microsecs::new(300).into_ns(), Gbps::new(5).into()),
+    ]);
+    let records = check_complete(minim::run(cfg)?);
+    // Every long flow is active during the outage and the capacity drop that follows it
+    for (before, after) in baseline.iter().zip(&records).take(NR_SOURCES) {
+        assert!(after.fct >= before.fct + Microsecs::new(100).into_ns());
+    }
+    Ok(())
+}
+
+#[test]
+fn permanent_outage_is_rejected() {
+    // The link would go down while the long flows are active and never come back up
+    let mut cfg = config(CcKind::Dctcp);
+    cfg.bandwidth_trace = BandwidthTrace::new(vec![
+        (Microsecs::new(200).into_ns(), Gbps::new(5).into()),
+        (Microsecs::new(300).into_ns(), BitsPerSec::ZERO),
+    ]);
+    assert!(minim::run(cfg).is_err());
+}
+
+// ACKs cross a slow reverse link, which carries enough data in the opposite direction to make
+// the ACKs queue.
+fn with_reverse_traffic(nr_reverse: usize) -> anyhow::Result<(Vec<Record>, Output)> {
+    let mut cfg = config(CcKind::Dctcp);
+    cfg.links = vec![LinkDesc::builder()
+        .id(LinkId::ONE)
+        .bandwidth(Gbps::new(1))
+        .delay(Nanosecs::new(1_000))
+        .dctcp_marking_threshold(Kilobytes::new(30))
+        .build()];
+    cfg.ack_path = Some(AckPath::builder().link(LinkId::ONE).build());
+    for i in NR_SOURCES..NR_SOURCES + nr_reverse {
+        cfg.sources.push(
+            SourceDesc::builder()
+                .id(SourceId::new(i))
+                .delay2btl(Nanosecs::new(1_000))
+                .link_rate(Gbps::new(10))
+                .build(),
+        );
+        cfg.flows.push(
+            FlowDesc::builder()
+                .id(FlowId::new(i + NR_SOURCES))
+                .source(SourceId::new(i))
+                .size(Bytes::new(100_000))
+                .start(Nanosecs::ZERO)
+                .delay2dst(Nanosecs::new(2_000))
+                .path(vec![LinkId::ONE])
+                .build(),
+        );
+    }
+    let mut output = minim::run_with_stats(cfg)?;
+    output.records.sort_by_key(|r| r.id);
+    let reverse = output.records.split_off(2 * NR_SOURCES);
+    assert_eq!(reverse.len(), nr_reverse);
+    assert!(reverse.iter().all(|r| r.fct >= r.ideal));
+    Ok((check_complete(output.records.clone()), output))
+}
+
+#[test]
+fn reverse_traffic_delays_acks() -> anyhow::Result<()> {
+    let (alone, _) = with_reverse_traffic(0)?;
+    let (records, output) = with_reverse_traffic(2)?;
+    // ACKs queue behind the reverse data, which slows down the forward flows
+    assert!(output.links[1].queues[0].max_occupancy > Bytes::ZERO);
+    let total = |records: &[Record]| records.iter().map(|r| r.fct).sum::<Nanosecs>();
+    assert!(total(&records) > total(&alone));
+    Ok(())
+}
+
+// Runs the incast over a fast core with 10 Gbps destination downlinks, spreading the flows across
+// `nr_dsts` destinations.
+fn with_destinations(nr_dsts: usize) -> anyhow::Result<Output> {
+    let mut cfg = config(CcKind::Dctcp);
+    cfg.bandwidth = Gbps::new(100).into();
+    cfg.links = (1..=2)
+        .map(|i| {
+            LinkDesc::builder()
+                .id(LinkId::new(i))
+                .bandwidth(Gbps::new(10))
+                .delay(Nanosecs::new(500))
+                .dctcp_marking_threshold(Kilobytes::new(30))
+                .build()
+        })
+        .collect();
+    cfg.destinations = (0..2)
+        .map(|i| {
+            DestDesc::builder()
+                .id(DestId::new(i))
+                .downlink(LinkId::new(i + 1))
+                .build()
+        })
+        .collect();
+    for (i, flow) in cfg.flows.iter_mut().enumerate() {
+        flow.dst = Some(DestId::new(i % nr_dsts));
+    }
+    Ok(minim::run_with_stats(cfg)?)
+}
+
+#[test]
+fn downlinks_isolate_destinations() -> anyhow::Result<()> {
+    let shared = with_destinations(1)?;
+    let split = with_destinations(2)?;
+    let shared_records = check_complete(shared.records);
+    let split_records = check_complete(split.records);
+    // The downlink is the bottleneck, so the ideal FCT is the same either way
+    assert!(shared_records
+        .iter()
+        .zip(&split_records)
+        .all(|(a, b)| a.ideal == b.ideal));
+    let last = |records: &[Record]| records.iter().map(|r| r.start + r.fct).max().unwrap();
+    assert!(last(&split_records) < last(&shared_records));
+    // Only the downlinks in use build queues
+    let occupancy = |links: &[LinkStats]| -> Vec<_> {
+        links[1..]
+            .iter()
+            .map(|l| l.queues[0].max_occupancy > Bytes::ZERO)
+            .collect()
+    };
+    assert_eq!(occupancy(&shared.links), [true, false]);
+    assert_eq!(occupancy(&split.links), [true, true]);
+    Ok(())
+}
@@ -0,0 +1,287 @@
+use minim::{
+    cc::CcKind,
+    units::{Bytes, Gbps, Kilobytes, Microsecs, Nanosecs},
+    workload::{Bursts, Generator, Pattern, SizeCdf},
+    BackgroundDesc, BackgroundId, CoflowId, FlowDesc, FlowId, FlowSchedule, Output, Record,
+    SourceId, TrafficPattern,
+};
+
+mod common;
+
+use common::{check_complete, config, NR_SOURCES};
+
+// Runs flows of the given sizes and weights from a single source, each arriving just after the
+// previous one, and returns their records in order.
+fn same_source(schedule: FlowSchedule, flows: &[(u64, u64)]) -> anyhow::Result<Vec<Record>> {
+    let mut cfg = config(CcKind::Dctcp);
+    cfg.sources.truncate(1);
+    cfg.sources[0].schedule = schedule;
+    cfg.flows = flows
+        .iter()
+        .enumerate()
+        .map(|(i, &(size, weight))| {
+            FlowDesc::builder()
+                .id(FlowId::new(i))
+                .source(SourceId::ZERO)
+                .size(Bytes::new(size))
+                .start(Nanosecs::new(i as u64))
+                .delay2dst(Nanosecs::new(2_000))
+                .weight(weight)
+                .build()
+        })
+        .collect();
+    let mut records = minim::run(cfg)?;
+    assert_eq!(records.len(), flows.len());
+    records.sort_by_key(|r| r.id);
+    Ok(records)
+}
+
+#[test]
+fn srpt_finishes_short_flow_first() -> anyhow::Result<()> {
+    let end = |r: &Record| r.start + r.fct;
+    // The short flow preempts the long one, so it completes about as fast as it would alone
+    let records = same_source(FlowSchedule::Srpt, &[(1_000_000, 1), (100_000, 1)])?;
+    let (long, short) = (&records[0], &records[1]);
+    assert!(short.fct.into_f64() < 1.05 * short.ideal.into_f64());
+    assert!(end(short) < end(long));
+    // Under FIFO, it waits for the long flow instead
+    let records = same_source(FlowSchedule::Fifo, &[(1_000_000, 1), (100_000, 1)])?;
+    let (long, short) = (&records[0], &records[1]);
+    assert!(end(short) >= end(long));
+    Ok(())
+}
+
+#[test]
+fn weighted_shares_follow_weights() -> anyhow::Result<()> {
+    // A short flow next to a long one gets its share of the source's link while both are active
+    for (schedule, weight, share) in [
+        (FlowSchedule::RoundRobin, 1, 0.5),
+        (FlowSchedule::Weighted, 1, 0.5),
+        (FlowSchedule::Weighted, 3, 0.75),
+    ] {
+        let records = same_source(schedule, &[(1_000_000, 1), (100_000, weight)])?;
+        let short = &records[1];
+        let slowdown = short.fct.into_f64() / short.ideal.into_f64();
+        assert!((slowdown * share - 1.0).abs() < 0.05);
+    }
+    // Two equal flows finish in the ratio of their shares: the heavier one sends its bytes at
+    // w/(w+1) of the link, and the lighter one finishes once both flows' bytes are sent
+    for weight in [1, 3] {
+        let records = same_source(
+            FlowSchedule::Weighted,
+            &[(1_000_000, 1), (1_000_000, weight)],
+        )?;
+        let ratio = records[1].fct.into_f64() / records[0].fct.into_f64();
+        let expected = (weight + 1) as f64 / (2 * weight) as f64;
+        assert!((ratio - expected).abs() < 0.02);
+    }
+    Ok(())
+}
+
+fn with_background(pattern: Option<TrafficPattern>) -> anyhow::Result<Output> {
+    let mut cfg = config(CcKind::Dctcp);
+    if let Some(pattern) = pattern {
+        cfg.background = vec![BackgroundDesc::builder()
+            .id(BackgroundId::ZERO)
+            .rate(Gbps::new(5))
+            .pattern(pattern)
+            .build()];
+    }
+    Ok(minim::run_with_stats(cfg)?)
+}
+
+#[test]
+fn background_traffic_competes_with_flows() -> anyhow::Result<()> {
+    let baseline = with_background(None)?;
+    let cbr = with_background(Some(TrafficPattern::Cbr))?;
+    let on_off = with_background(Some(TrafficPattern::OnOff {
+        mean_on: Microsecs::new(100).into_ns(),
+        mean_off: Microsecs::new(100).into_ns(),
+    }))?;
+    let total = |output: &Output| output.records.iter().map(|r| r.fct).sum::<Nanosecs>();
+    assert!(total(&baseline) < total(&on_off));
+    assert!(total(&on_off) < total(&cbr));
+    // Background bytes are accounted separately, and the streams stop once the flows are done
+    assert!(baseline.background.is_empty());
+    for output in [&cbr, &on_off] {
+        check_complete(output.records.clone());
+        assert!(output.records.iter().all(|r| r.drops == 0));
+        let stats = output.background[0];
+        assert!(stats.sent > Bytes::ZERO);
+        assert_eq!(stats.delivered + stats.dropped, stats.sent);
+    }
+    let last = |output: &Output| {
+        output
+            .records
+            .iter()
+            .map(|r| r.start + r.fct)
+            .max()
+            .unwrap()
+    };
+    // CBR traffic doesn't back off, so it takes half of the link for as long as the flows last,
+    // and the flows take twice as long to drain through the other half
+    let expected = Gbps::new(5).into_bps().width(last(&cbr));
+    assert!(cbr.background[0].sent.into_f64() > 0.95 * expected.into_f64());
+    let slowdown = last(&cbr).into_f64() / last(&baseline).into_f64();
+    assert!((1.9..2.1).contains(&slowdown));
+    // An on/off stream is on half of the time on average, and the flows use the rest of the link
+    let on_off_sent = on_off.background[0].sent;
+    let duty_cycle =
+        on_off_sent.into_f64() / Gbps::new(5).into_bps().width(last(&on_off)).into_f64();
+    assert!((0.35..0.65).contains(&duty_cycle));
+    let expected = last(&baseline) + Gbps::new(10).into_bps().length(on_off_sent);
+    assert!((last(&on_off).into_f64() / expected.into_f64() - 1.0).abs() < 0.05);
+    Ok(())
+}
+
+// Runs a workload generated at `load` for 10 ms, returning the records.
+fn generated(load: f64) -> anyhow::Result<Vec<Record>> {
+    let mut cfg = config(CcKind::Dctcp);
+    let sizes = SizeCdf::new(vec![
+        (Bytes::new(1_000), 0.0),
+        (Bytes::new(10_000), 0.5),
+        (Bytes::new(100_000), 1.0),
+    ])?;
+    cfg.flows = Generator::builder()
+        .sources(cfg.sources.clone())
+        .sizes(sizes)
+        .load(load)
+        .bandwidth(cfg.bandwidth)
+        .duration(Microsecs::new(10_000).into_ns())
+        .btl2dst(Nanosecs::new(1_000))
+        .seed(1)
+        .build()
+        .generate()?;
+    let nr_flows = cfg.flows.len();
+    assert!(nr_flows > 0);
+    let records = minim::run(cfg)?;
+    assert_eq!(records.len(), nr_flows);
+    assert!(records.iter().all(|r| r.fct >= r.ideal));
+    Ok(records)
+}
+
+#[test]
+fn generated_load_matches_target() -> anyhow::Result<()> {
+    let duration = Microsecs::new(10_000).into_ns();
+    let mean_slowdown = |records: &[Record]| {
+        let total = records
+            .iter()
+            .map(|r| r.fct.into_f64() / r.ideal.into_f64())
+            .sum::<f64>();
+        total / records.len() as f64
+    };
+    let mut slowdowns = Vec::new();
+    for load in [0.2, 0.8] {
+        let records = generated(load)?;
+        // The flows offer the target fraction of the bottleneck's capacity
+        let bytes = records.iter().map(|r| r.size).sum::<Bytes>();
+        let offered = bytes.into_f64() / Gbps::new(10).into_bps().width(duration).into_f64();
+        assert!((offered / load - 1.0).abs() < 0.1);
+        // The bottleneck keeps up, so the last flows complete soon after the arrivals stop
+        let last = records.iter().map(|r| r.start + r.fct).max().unwrap();
+        assert!(last < duration + Microsecs::new(1_000).into_ns());
+        slowdowns.push(mean_slowdown(&records));
+    }
+    // Flows queue behind each other more often at a higher load
+    assert!(slowdowns[0] < slowdowns[1]);
+    Ok(())
+}
+
+// Runs three bursts of 64 KB flows, returning the records and the largest bottleneck queue.
+fn bursts(pattern: Pattern, nr_senders: usize) -> anyhow::Result<(Vec<Record>, Bytes)> {
+    let mut cfg = config(CcKind::Dctcp);
+    cfg.flows = Bursts::builder()
+        .sources(cfg.sources.clone())
+        .pattern(pattern)
+        .nr_senders(nr_senders)
+        .size(Kilobytes::new(64))
+        .period(Microsecs::new(500).into_ns())
+        .nr_bursts(3)
+        .btl2dst(Nanosecs::new(1_000))
+        .build()
+        .generate()?;
+    let nr_flows = cfg.flows.len();
+    let output = minim::run_with_stats(cfg)?;
+    assert_eq!(output.records.len(), nr_flows);
+    Ok((output.records, output.queues[0].max_occupancy))
+}
+
+#[test]
+fn incast_scales_with_senders() -> anyhow::Result<()> {
+    // Each flow takes about 54 us to send 64 KB and its headers at 10 Gbps
+    let flow_time = Gbps::new(10).into_bps().length(Bytes::new(64 * 1048));
+    let (records_2, queue_2) = bursts(Pattern::Incast, 2)?;
+    let (records_8, queue_8) = bursts(Pattern::Incast, 8)?;
+    // Every sender's first window arrives at once, so the peak queue grows with the fan-in
+    assert!(queue_8.into_f64() > 3.0 * queue_2.into_f64());
+    // The senders share the bottleneck, so a burst takes about as long as its flows back to back
+    for (records, nr_senders) in [(records_2, 2), (records_8, 8)] {
+        let expected = flow_time.scale_by(nr_senders as f64).into_f64();
+        let last = records.iter().map(|r| r.fct).max().unwrap().into_f64();
+        assert!((1.0..1.1).contains(&(last / expected)));
+    }
+    // All-to-one bursts involve every source, whatever the number of senders
+    let (records_all, queue_all) = bursts(Pattern::AllToOne, 1)?;
+    assert_eq!(records_all.len(), 3 * NR_SOURCES);
+    assert_eq!(queue_all, queue_8);
+    Ok(())
+}
+
+#[test]
+fn dependent_flows_start_after_dependencies() -> anyhow::Result<()> {
+    // Each short flow responds to a long flow after a think time
+    let think_time = Microsecs::new(5).into_ns();
+    let mut cfg = config(CcKind::Dctcp);
+    for flow in &mut cfg.flows[NR_SOURCES..] {
+        flow.depends_on = vec![FlowId::new(flow.id.into_usize() - NR_SOURCES)];
+        flow.think_time = think_time;
+    }
+    let records = check_complete(minim::run(cfg)?);
+    for (request, response) in records[..NR_SOURCES].iter().zip(&records[NR_SOURCES..]) {
+        assert_eq!(response.start, request.start + request.fct + think_time);
+    }
+    // A flow with several dependencies waits for the last one, and never starts before its
+    // own start time
+    let late_start = Microsecs::new(50_000).into_ns();
+    let mut cfg = config(CcKind::Dctcp);
+    cfg.flows[NR_SOURCES].depends_on = (0..NR_SOURCES).map(FlowId::new).collect();
+    cfg.flows[NR_SOURCES].think_time = think_time;
+    cfg.flows[NR_SOURCES + 1].depends_on = vec![FlowId::new(1)];
+    cfg.flows[NR_SOURCES + 1].start = late_start;
+    let records = check_complete(minim::run(cfg)?);
+    let last_end = records[..NR_SOURCES]
+        .iter()
+        .map(|r| r.start + r.fct)
+        .max()
+        .unwrap();
+    assert!(last_end + think_time < late_start);
+    assert_eq!(records[NR_SOURCES].start, last_end + think_time);
+    assert_eq!(records[NR_SOURCES + 1].start, late_start);
+    // Dependencies must be acyclic
+    let mut cfg = config(CcKind::Dctcp);
+    cfg.flows[0].depends_on = vec![FlowId::new(NR_SOURCES)];
+    cfg.flows[NR_SOURCES].depends_on = vec![FlowId::new(0)];
+    assert!(minim::run(cfg).is_err());
+    Ok(())
+}
+
+#[test]
+fn coflows_complete_with_slowest_flow() -> anyhow::Result<()> {
+    // The long flows and the short flows form two coflows
+    let mut cfg = config(CcKind::Dctcp);
+    for flow in &mut cfg.flows {
+        flow.coflow = Some(CoflowId::new(flow.id.into_usize() / NR_SOURCES));
+    }
+    let output = minim::run_with_stats(cfg)?;
+    let records = check_complete(output.records);
+    assert_eq!(output.coflows.len(), 2);
+    for (coflow, flows) in output.coflows.iter().zip(records.chunks(NR_SOURCES)) {
+        assert_eq!(coflow.nr_flows, NR_SOURCES);
+        assert_eq!(coflow.size, flows.iter().map(|r| r.size).sum());
+        let end = flows.iter().map(|r| r.start + r.fct).max().unwrap();
+        assert_eq!(coflow.start + coflow.cct, end);
+        assert!(flows.iter().all(|r| coflow.ideal >= r.ideal));
+        assert!(coflow.cct >= coflow.ideal);
+    }
+    Ok(())
+}